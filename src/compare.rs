@@ -0,0 +1,142 @@
+extern crate serde_json;
+
+use graph::plan_queries;
+use Optimizations;
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::Instant;
+
+
+/// How a comparison table is written out.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    Text,
+    Csv,
+    Json,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(ReportFormat::Text),
+            "csv" => Ok(ReportFormat::Csv),
+            "json" => Ok(ReportFormat::Json),
+            _ => Err(format!("unknown report format '{}' (expected text, csv or json)", s)),
+        }
+    }
+}
+
+impl fmt::Display for ReportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReportFormat::Text => write!(f, "text"),
+            ReportFormat::Csv => write!(f, "csv"),
+            ReportFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// Metrics for one planning run of a workload under a single `Optimizations` setting.
+#[derive(Clone, Debug, Serialize)]
+pub struct StrategyResult {
+    pub strategy: String,
+    pub opts: Optimizations,
+    pub nodes: usize,
+    pub joins: usize,
+    pub outer_joins: usize,
+    pub rows: usize,
+    pub bytes: usize,
+    pub reused: usize,
+    /// Fraction of non-base nodes used by more than one query.
+    pub shared: f64,
+    /// `rows`, `bytes` and `nodes` weighted by the cost model.
+    pub cost: f64,
+    pub planning_ms: f64,
+}
+
+/// Plans `queries` once per meaningful `Optimizations` combination, each using the table
+/// statistics and cost model of `base`.
+pub fn compare_strategies(queries: &[String], base: &Optimizations) -> Vec<StrategyResult> {
+    Optimizations::all_combinations()
+        .into_iter()
+        .map(|combo| {
+            let opts = Optimizations { statistics: base.statistics.clone(), cost: base.cost.clone(), ..combo };
+            let start = Instant::now();
+            let report = plan_queries(queries, &opts);
+            let elapsed = start.elapsed();
+            StrategyResult {
+                strategy: opts.label(),
+                nodes: report.num_nodes(),
+                joins: report.num_joins(),
+                outer_joins: report.num_outer_joins(),
+                rows: report.total_rows(),
+                bytes: report.total_bytes(),
+                reused: report.total_reused(),
+                shared: report.shared_fraction(),
+                cost: opts.cost.cost(&report),
+                planning_ms: elapsed.as_secs_f64() * 1000.0,
+                opts,
+            }
+        })
+        .collect()
+}
+
+const COLUMNS: [&str; 10] = ["strategy", "nodes", "joins", "outer_joins", "rows", "bytes", "reused", "shared", "cost",
+                             "planning_ms"];
+
+fn row_values(r: &StrategyResult) -> Vec<String> {
+    vec![
+        r.strategy.clone(),
+        r.nodes.to_string(),
+        r.joins.to_string(),
+        r.outer_joins.to_string(),
+        r.rows.to_string(),
+        r.bytes.to_string(),
+        r.reused.to_string(),
+        format!("{:.3}", r.shared),
+        format!("{:.1}", r.cost),
+        format!("{:.3}", r.planning_ms),
+    ]
+}
+
+/// Renders a comparison table in the requested format.
+pub fn format_comparison(results: &[StrategyResult], format: ReportFormat) -> String {
+    match format {
+        ReportFormat::Json => serde_json::to_string_pretty(results).unwrap(),
+        ReportFormat::Csv => {
+            let mut s = COLUMNS.join(",");
+            s.push('\n');
+            for r in results {
+                s.push_str(&row_values(r).join(","));
+                s.push('\n');
+            }
+            s
+        }
+        ReportFormat::Text => {
+            let rows: Vec<Vec<String>> = results.iter().map(row_values).collect();
+            let widths: Vec<usize> = COLUMNS.iter()
+                                            .enumerate()
+                                            .map(|(i, c)| rows.iter().map(|r| r[i].len()).fold(c.len(), usize::max))
+                                            .collect();
+            let mut s = String::new();
+            let header: Vec<String> = COLUMNS.iter().map(|c| c.to_string()).collect();
+            for row in Some(&header).into_iter().chain(rows.iter()) {
+                let cells: Vec<String> = row.iter()
+                                            .enumerate()
+                                            .map(|(i, v)| if i == 0 {
+                                                format!("{:<w$}", v, w = widths[i])
+                                            } else {
+                                                format!("{:>w$}", v, w = widths[i])
+                                            })
+                                            .collect();
+                s.push_str(cells.join("  ").trim_end());
+                s.push('\n');
+            }
+            s
+        }
+    }
+}
@@ -0,0 +1,226 @@
+extern crate toml;
+
+use compare::ReportFormat;
+use export::get_exporter;
+use graph::Graph;
+use graphviz::DotOptions;
+use pipeline::{Pass, Pipeline};
+use report::PlanReport;
+use Optimizations;
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+
+/// Row counts assumed for base tables.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Statistics {
+    /// Rows per table, by name. Setting this replaces the built-in TPC-W sizes entirely.
+    pub rows: BTreeMap<String, usize>,
+    /// Rows assumed for tables not in `rows`.
+    pub default_rows: usize,
+}
+
+impl Statistics {
+    pub fn rows_for(&self, table: &str) -> usize {
+        self.rows.get(table).cloned().unwrap_or(self.default_rows)
+    }
+}
+
+impl Default for Statistics {
+    /// TPC-W table sizes, and 10 rows for anything else.
+    fn default() -> Self {
+        let rows = [("customer", 2880000),
+                    ("orders", 2592000),
+                    ("order_line", 7775551),
+                    ("item", 10000),
+                    ("author", 625),
+                    ("cc_xacts", 2592000),
+                    ("country", 92),
+                    ("address", 5760000)]
+            .iter()
+            .map(|&(name, rows)| (String::from(name), rows))
+            .collect();
+        Statistics { rows, default_rows: 10 }
+    }
+}
+
+/// Weights that combine a plan's metrics into the single `cost` reported by `compare`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CostModel {
+    pub node_weight: f64,
+    pub row_weight: f64,
+    pub byte_weight: f64,
+}
+
+impl CostModel {
+    pub fn cost(&self, report: &PlanReport) -> f64 {
+        self.graph_cost(&report.graph)
+    }
+
+    /// The cost of `graph` as it stands, which lets the planner compare alternative plans for
+    /// a query by planning each on a copy of the graph.
+    pub fn graph_cost(&self, graph: &Graph) -> f64 {
+        self.node_weight * graph.len() as f64
+            + self.row_weight * graph.total_rows() as f64
+            + self.byte_weight * graph.total_bytes() as f64
+    }
+}
+
+impl Default for CostModel {
+    /// Cost is the number of rows held in non-base nodes.
+    fn default() -> Self {
+        CostModel { node_weight: 0.0, row_weight: 1.0, byte_weight: 0.0 }
+    }
+}
+
+/// The `[planner]` section: the same choices as the command-line flags.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlannerConfig {
+    pub overlap: bool,
+    pub permutations: bool,
+    pub sorted_names: bool,
+    pub nonprefix: bool,
+    pub megajoin: bool,
+    pub passes: Option<Vec<Pass>>,
+    pub strategy: Option<String>,
+}
+
+/// The `[output]` section: where the graph goes and how it is rendered.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    /// File the graphviz source is written to; each rendered format goes to `<name>.<format>`.
+    pub name: String,
+    /// Graphviz layout program to render with, or `native` for the built-in SVG layout.
+    pub renderer: String,
+    /// Comma-separated output formats. `dot`, `graphml`, `json` and `mermaid` are exported
+    /// directly; the rest are passed to the renderer as `-T<format>`.
+    pub format: String,
+    /// Also save the planned graph as JSON to this file.
+    pub save_graph: Option<String>,
+    /// Draw each query's nodes in a cluster of their own.
+    pub clustered: bool,
+    /// Also write the graph after every statement, see `StepWriter`.
+    pub steps: bool,
+}
+
+impl OutputConfig {
+    pub fn formats(&self) -> Vec<&str> {
+        self.format.split(',').map(|f| f.trim()).filter(|f| !f.is_empty()).collect()
+    }
+
+    /// File `format` is written to: `<name>.<extension>` for exported formats, otherwise
+    /// `<name>.<format>`.
+    pub fn rendered_name(&self, format: &str) -> String {
+        match get_exporter(format, &self.dot_options()) {
+            Some(exporter) => format!("{}.{}", self.name, exporter.extension()),
+            None => format!("{}.{}", self.name, format),
+        }
+    }
+
+    pub fn dot_options(&self) -> DotOptions {
+        DotOptions { clustered: self.clustered, ..DotOptions::default() }
+    }
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        OutputConfig {
+            name: String::from("graph"),
+            renderer: String::from("dot"),
+            format: String::from("pdf"),
+            save_graph: None,
+            clustered: false,
+            steps: false,
+        }
+    }
+}
+
+/// The `[report]` section.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReportConfig {
+    /// Name the workload is announced under.
+    pub label: String,
+    /// Format of the `compare` table.
+    pub format: ReportFormat,
+}
+
+impl Default for ReportConfig {
+    fn default() -> Self {
+        ReportConfig { label: String::from("undefined"), format: ReportFormat::Text }
+    }
+}
+
+/// One experiment: the workload, how to plan it and where the results go. Every field is
+/// optional, and the defaults match running the binary with no flags.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Workload file to plan.
+    pub input: Option<String>,
+    /// File of `CREATE TABLE` statements planned before the workload, for recipes that keep
+    /// their schema apart from their queries.
+    pub schema: Option<String>,
+    pub planner: PlannerConfig,
+    pub cost: CostModel,
+    pub statistics: Statistics,
+    pub output: OutputConfig,
+    pub report: ReportConfig,
+}
+
+impl Config {
+    pub fn from_toml(s: &str) -> io::Result<Self> {
+        toml::from_str(s).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut s = String::new();
+        File::open(path)?.read_to_string(&mut s)?;
+        Config::from_toml(&s)
+    }
+
+    pub fn optimizations(&self) -> Optimizations {
+        let p = &self.planner;
+        Optimizations {
+            overlap: p.overlap,
+            permutations: p.permutations,
+            sorted_names: p.sorted_names,
+            nonprefix: p.nonprefix,
+            megajoin: p.megajoin,
+            passes: p.passes.clone().map(Pipeline::new),
+            join_strategy: p.strategy.clone(),
+            statistics: self.statistics.clone(),
+            cost: self.cost.clone(),
+        }
+    }
+}
+
+
+#[test]
+fn test_load_config() {
+    use graph::plan_queries;
+    use load_queries;
+
+    let config = Config::load(Path::new("tests/tpc-w.toml")).unwrap();
+    assert_eq!(config.output.formats(), vec!["svg"]);
+    assert_eq!(config.output.rendered_name("svg"), "tpcw.svg");
+    assert_eq!(config.report.format, ReportFormat::Csv);
+    let opts = config.optimizations();
+    assert_eq!(opts.pipeline().label(), "canonicalize-names>match-subsets>choose-join-order>match-reuse");
+
+    let report = plan_queries(&load_queries(Path::new(config.input.as_ref().unwrap())).unwrap(), &opts);
+    assert_eq!(report.graph[report.tables["address"]].maxrows, 5760000);
+    assert_eq!(report.graph[report.tables["shopping_cart"]].maxrows, 100);
+    assert!(opts.cost.cost(&report) > report.total_rows() as f64);
+
+    // a missing section falls back to its defaults, a misspelled key is an error
+    assert_eq!(Config::from_toml("[planner]\noverlap = true").unwrap().output, OutputConfig::default());
+    assert!(Config::from_toml("[planner]\noverlaps = true").is_err());
+}
@@ -0,0 +1,257 @@
+extern crate serde_json;
+
+use graph::{Graph, TestNode};
+use graphviz::{graphviz_with, shorten, DotOptions, MAX_LABEL_COLUMNS, MAX_QUERY_NAME};
+
+
+/// The attributes every export format carries for a node.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExportedNode {
+    pub id: String,
+    pub name: String,
+    pub kind: String,
+    pub rows: usize,
+    pub columns: Vec<String>,
+    pub users: Vec<String>,
+}
+
+impl ExportedNode {
+    pub fn new(node: &TestNode) -> Self {
+        ExportedNode {
+            id: format!("n{}", node.index),
+            name: node.name.clone(),
+            kind: String::from(node.kind()),
+            rows: node.maxrows,
+            columns: node.columns.iter().map(|c| String::from(c.output_name())).collect(),
+            users: node.users.iter().cloned().collect(),
+        }
+    }
+}
+
+/// An edge from an ancestor to its child, by node id.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExportedLink {
+    pub source: String,
+    pub target: String,
+}
+
+/// A graph in the node-link layout read by networkx's `node_link_graph`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NodeLinkGraph {
+    pub directed: bool,
+    pub multigraph: bool,
+    pub nodes: Vec<ExportedNode>,
+    pub links: Vec<ExportedLink>,
+}
+
+impl NodeLinkGraph {
+    pub fn new(graph: &Graph) -> Self {
+        NodeLinkGraph {
+            directed: true,
+            multigraph: false,
+            nodes: graph.iter().map(ExportedNode::new).collect(),
+            links: graph.iter()
+                        .flat_map(|node| node.children.iter().map(move |&child| ExportedLink {
+                            source: format!("n{}", node.index),
+                            target: format!("n{}", child),
+                        }))
+                        .collect(),
+        }
+    }
+}
+
+/// Writes a planned graph as text in one format.
+///
+/// Look exporters up by format name with `get_exporter`; `render` uses them for every format
+/// they cover and passes the rest to the renderer.
+pub trait Exporter {
+    /// Format name, as given to `--format`.
+    fn name(&self) -> &str;
+
+    /// Extension of the files this format is written to.
+    fn extension(&self) -> &str {
+        self.name()
+    }
+
+    fn export(&self, graph: &Graph) -> String;
+}
+
+/// The graphviz source, as `graphviz_with` writes it.
+pub struct DotExporter {
+    pub options: DotOptions,
+}
+
+impl Exporter for DotExporter {
+    fn name(&self) -> &str {
+        "dot"
+    }
+
+    fn export(&self, graph: &Graph) -> String {
+        graphviz_with(graph, &self.options)
+    }
+}
+
+/// A Mermaid flowchart, for embedding in markdown. Labels list the first few columns and
+/// abbreviate query names like the DOT labels do.
+pub struct MermaidExporter;
+
+/// `s` with the characters Mermaid treats as markup replaced by entity codes.
+fn mermaid_escape(s: &str) -> String {
+    s.replace('&', "#amp;")
+     .replace('"', "#quot;")
+     .replace('<', "#lt;")
+     .replace('>', "#gt;")
+}
+
+impl Exporter for MermaidExporter {
+    fn name(&self) -> &str {
+        "mermaid"
+    }
+
+    fn extension(&self) -> &str {
+        "mmd"
+    }
+
+    fn export(&self, graph: &Graph) -> String {
+        let mut s = String::from("flowchart TD\n");
+        for node in graph {
+            let n = ExportedNode::new(node);
+            let mut lines = vec![format!("{} {}", node.symbol(), n.name),
+                                 format!("kind: {}", n.kind),
+                                 format!("rows: {}", n.rows)];
+            if !n.columns.is_empty() {
+                let mut columns: Vec<String> = n.columns.iter().take(MAX_LABEL_COLUMNS).cloned().collect();
+                if n.columns.len() > MAX_LABEL_COLUMNS {
+                    columns.push(format!("+{} more", n.columns.len() - MAX_LABEL_COLUMNS));
+                }
+                lines.push(format!("columns: {}", columns.join(", ")));
+            }
+            if !n.users.is_empty() {
+                let users: Vec<String> = n.users.iter().map(|u| shorten(u, MAX_QUERY_NAME)).collect();
+                lines.push(format!("users: {}", users.join(", ")));
+            }
+            let label = lines.iter().map(|l| mermaid_escape(l)).collect::<Vec<_>>().join("<br/>");
+            let (open, close) = match n.kind.as_str() {
+                "base" => ("[(", ")]"),
+                "project" => ("([", "])"),
+                "leaf" => ("{{", "}}"),
+                _ => ("[", "]"),
+            };
+            s.push_str(&format!("    {}{}\"{}\"{}\n", n.id, open, label, close));
+        }
+        for link in NodeLinkGraph::new(graph).links {
+            s.push_str(&format!("    {} --> {}\n", link.source, link.target));
+        }
+        s
+    }
+}
+
+/// GraphML, for tools like networkx, yEd or Gephi. Columns and users are comma-separated.
+pub struct GraphMlExporter;
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+     .replace('<', "&lt;")
+     .replace('>', "&gt;")
+     .replace('"', "&quot;")
+}
+
+/// GraphML keys for the node attributes, as (id, type).
+const GRAPHML_KEYS: [(&str, &str); 5] = [("name", "string"), ("kind", "string"), ("rows", "long"),
+                                         ("columns", "string"), ("users", "string")];
+
+impl Exporter for GraphMlExporter {
+    fn name(&self) -> &str {
+        "graphml"
+    }
+
+    fn export(&self, graph: &Graph) -> String {
+        let mut s = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                                  <graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
+        for &(key, ty) in GRAPHML_KEYS.iter() {
+            s.push_str(&format!("  <key id=\"{}\" for=\"node\" attr.name=\"{}\" attr.type=\"{}\"/>\n", key, key, ty));
+        }
+        s.push_str("  <graph id=\"G\" edgedefault=\"directed\">\n");
+        let graph = NodeLinkGraph::new(graph);
+        for n in graph.nodes.iter() {
+            let values = [n.name.clone(), n.kind.clone(), n.rows.to_string(), n.columns.join(","), n.users.join(",")];
+            s.push_str(&format!("    <node id=\"{}\">\n", n.id));
+            for (&(key, _), value) in GRAPHML_KEYS.iter().zip(values.iter()) {
+                s.push_str(&format!("      <data key=\"{}\">{}</data>\n", key, xml_escape(value)));
+            }
+            s.push_str("    </node>\n");
+        }
+        for link in graph.links.iter() {
+            s.push_str(&format!("    <edge source=\"{}\" target=\"{}\"/>\n", link.source, link.target));
+        }
+        s.push_str("  </graph>\n</graphml>\n");
+        s
+    }
+}
+
+/// Node-link JSON, see `NodeLinkGraph`.
+pub struct JsonGraphExporter;
+
+impl Exporter for JsonGraphExporter {
+    fn name(&self) -> &str {
+        "json"
+    }
+
+    fn export(&self, graph: &Graph) -> String {
+        serde_json::to_string_pretty(&NodeLinkGraph::new(graph)).unwrap() + "\n"
+    }
+}
+
+/// Names of the formats `get_exporter` knows.
+pub fn exporter_names() -> Vec<&'static str> {
+    vec!["dot", "graphml", "json", "mermaid"]
+}
+
+/// The exporter for `format`, or `None` if it has to be produced by a renderer. `options`
+/// only affects DOT.
+pub fn get_exporter(format: &str, options: &DotOptions) -> Option<Box<dyn Exporter>> {
+    match format {
+        "dot" => Some(Box::new(DotExporter { options: options.clone() })),
+        "graphml" => Some(Box::new(GraphMlExporter)),
+        "json" => Some(Box::new(JsonGraphExporter)),
+        "mermaid" => Some(Box::new(MermaidExporter)),
+        _ => None,
+    }
+}
+
+
+#[test]
+fn test_exporters() {
+    use graph::plan_queries;
+    use std::path::Path;
+    use {load_queries, Optimizations};
+
+    let report = plan_queries(&load_queries(Path::new("tests/combo-join.txt")).unwrap(), &Optimizations::new());
+    let graph = &report.graph;
+    let edges: usize = graph.iter().map(|n| n.children.len()).sum();
+    for name in exporter_names() {
+        assert_eq!(get_exporter(name, &DotOptions::default()).unwrap().name(), name);
+    }
+    assert!(get_exporter("pdf", &DotOptions::default()).is_none());
+
+    // node-link JSON reads back with every node's attributes
+    let json: NodeLinkGraph = serde_json::from_str(&JsonGraphExporter.export(graph)).unwrap();
+    assert_eq!(json.nodes.len(), report.num_nodes());
+    assert_eq!(json.links.len(), edges);
+    let shared = graph.iter().find(|n| n.users.len() > 1 && !n.is_base()).unwrap();
+    let exported = &json.nodes[shared.index];
+    assert_eq!(exported.kind, shared.kind());
+    assert_eq!(exported.rows, shared.maxrows);
+    assert_eq!(exported.users.len(), shared.users.len());
+
+    let graphml = GraphMlExporter.export(graph);
+    assert_eq!(graphml.matches("<node ").count(), report.num_nodes());
+    assert_eq!(graphml.matches("<edge ").count(), edges);
+    assert!(graphml.contains(&format!("<data key=\"users\">{}</data>", xml_escape(&exported.users.join(",")))));
+
+    let mermaid = MermaidExporter.export(graph);
+    assert!(mermaid.starts_with("flowchart TD\n"));
+    assert_eq!(mermaid.matches(" --> ").count(), edges);
+    assert!(mermaid.contains(&format!("    n{}[(\"B ", graph.iter().find(|n| n.is_base()).unwrap().index)));
+    assert!(!mermaid.lines().skip(1).any(|l| l.matches('"').count() != 2 && !l.contains("-->")));
+}
@@ -2,82 +2,198 @@ extern crate nom_sql;
 
 use nom_sql::SqlQuery;
 use nom_sql::{SelectStatement, SelectSpecification, CreateTableStatement, CreateViewStatement,
-    FieldDefinitionExpression, JoinRightSide, TableKey};
+    FieldDefinitionExpression, FieldValueExpression, JoinRightSide, TableKey};
+use nom_sql::{ArithmeticBase, ArithmeticExpression};
+use nom_sql::{ColumnConstraint, ColumnSpecification, ConditionBase, ConditionExpression, FunctionExpression, Literal,
+    Operator};
+use config::Statistics;
 use graphviz::graphviz;
-use join;
+use strategy::{self, JoinPredicate, JoinStrategy};
+use report::{Location, ParseFailure, PlanError, PlanReport, QueryResult, Removal};
+use inline::{self, InlinedView};
+use prune;
+use resolve::{Resolution, ResolvedColumn, resolve_select};
+use snapshot::{Step, StepWriter};
 use Optimizations;
 
-use std::collections::HashMap;
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::collections::{BTreeSet, HashMap};
 use std::fmt;
 use std::fs::File;
 use std::io::Write;
+use std::ops::{Index, IndexMut};
 use std::path::Path;
+use std::slice;
 
 
 
 
 
-#[derive(Clone, Debug, PartialEq)]
+/// Assumed width of a single column value when estimating state size.
+pub const BYTES_PER_COLUMN: usize = 8;
+
+/// A column of a node's output.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Column {
     pub name: String,
+    /// Table or view the column is read from; `None` for columns a query computes.
+    #[serde(default)]
+    pub table: Option<String>,
+    /// Name the query outputs the column under, if it renames it.
+    #[serde(default)]
+    pub alias: Option<String>,
+    /// Declared type, as nom-sql prints it; `None` where it is not known.
+    #[serde(default)]
+    pub sql_type: Option<String>,
+    #[serde(default = "nullable_default")]
+    pub nullable: bool,
+    /// The base table columns the values are computed from.
+    #[serde(default)]
+    pub lineage: Vec<BaseColumn>,
+    /// For computed columns, the columns of the query's relations they are computed from, as
+    /// `table.column`.
+    #[serde(default)]
+    pub inputs: Vec<String>,
+}
+
+fn nullable_default() -> bool {
+    true
+}
+
+/// A column of a base table.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct BaseColumn {
+    pub table: String,
+    pub name: String,
+}
+
+impl fmt::Display for BaseColumn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.table, self.name)
+    }
+}
+
+impl Column {
+    /// A column known only by name, such as a reader key.
+    pub fn new(name: &str) -> Column {
+        Column {
+            name: String::from(name),
+            table: None,
+            alias: None,
+            sql_type: None,
+            nullable: true,
+            lineage: Vec::new(),
+            inputs: Vec::new(),
+        }
+    }
+
+    /// The column `spec` declares on base table `table`.
+    pub fn base(table: &str, spec: &ColumnSpecification) -> Column {
+        let not_null = spec.constraints.iter()
+                           .any(|c| *c == ColumnConstraint::NotNull || *c == ColumnConstraint::PrimaryKey);
+        Column {
+            name: spec.column.name.clone(),
+            table: Some(String::from(table)),
+            alias: None,
+            sql_type: Some(spec.sql_type.to_string()),
+            nullable: !not_null,
+            lineage: vec![BaseColumn { table: String::from(table), name: spec.column.name.clone() }],
+            inputs: Vec::new(),
+        }
+    }
+
+    /// A computed column named `name`, derived from the base columns of `inputs`.
+    pub fn computed<'a, I: IntoIterator<Item = &'a Column>>(name: &str, sql_type: Option<String>, nullable: bool,
+                                                          inputs: I) -> Column {
+        let inputs: Vec<&Column> = inputs.into_iter().collect();
+        let mut lineage: Vec<BaseColumn> = inputs.iter().flat_map(|c| c.lineage.iter().cloned()).collect();
+        lineage.sort();
+        lineage.dedup();
+        let inputs = inputs.iter().map(|c| c.qualified_name()).collect();
+        Column { name: String::from(name), table: None, alias: None, sql_type, nullable, lineage, inputs }
+    }
+
+    /// The name the column is output under.
+    pub fn output_name(&self) -> &str {
+        self.alias.as_ref().unwrap_or(&self.name)
+    }
+
+    /// `table.name`, or just the name for computed columns.
+    pub fn qualified_name(&self) -> String {
+        match self.table {
+            Some(ref table) => format!("{}.{}", table, self.name),
+            None => self.name.clone(),
+        }
+    }
+
+    /// Whether both columns are read from the same column of the same relation.
+    pub fn same_source(&self, other: &Column) -> bool {
+        self.table == other.table && self.name == other.name
+    }
 }
 
+/// Position of a node in its `Graph`; always equal to the node's `index` field.
+pub type NodeIndex = usize;
+
 #[derive(Clone)]
 pub struct TestNode {
     pub name: String,
-    pub index: usize,
+    pub index: NodeIndex,
     pub data: TestNodeData,
     pub columns: Vec<Column>,
-    pub ancestors: Vec<TestNodeRef>,
-    pub children: Vec<TestNodeRef>,
+    pub ancestors: Vec<NodeIndex>,
+    pub children: Vec<NodeIndex>,
     pub maxrows: usize,
+    /// Names of the queries and views whose plans include this node.
+    pub users: BTreeSet<String>,
+    /// Column equalities a join matches rows on; empty for other nodes.
+    pub join_keys: Vec<JoinPredicate>,
+    /// Columns a leaf's readers look rows up by, from the query's `?` parameters; empty for
+    /// other nodes.
+    pub reader_key: Vec<Column>,
 }
-pub type TestNodeRef = Rc<RefCell<TestNode>>;
 
 impl fmt::Debug for TestNode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        /*let column_strings: Vec<String> = self.columns.iter().map(|a| a.clone().name).collect();
-        let ancestor_strings: Vec<String> = self.ancestors.iter().map(|a| a.borrow().clone().name).collect();
-        let children_strings: Vec<String> = self.children.iter().map(|a| a.borrow().clone().name).collect();
-        write!(f, "TestNode {{ name: {}, data: {:?}, columns: {:?}, ancestors: {:?}, children: {:?} }}",
-                self.name,
-                self.data,
-                column_strings,
-                ancestor_strings,
-                children_strings
-            )*/
         write!(f, "{}: {:?}, {}", self.name, self.data, self.maxrows)
     }
 }
 
-impl fmt::Display for TestNode {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        indented_print(self, 0, f)
+impl TestNode {
+    /// Text shown for this node in rendered graphs.
+    pub fn label(&self) -> String {
+        format!("{} {}", self.name, self.maxrows)
     }
-}
 
-pub fn indented_print(node: &TestNode, indent:usize, f: &mut fmt::Formatter) -> fmt::Result {
-    let spaces = (0..indent*3).map(|_| " ").collect::<String>();
-    write!(f, "{}-- {}:{:?}\n", spaces, node.name, node.data);
-    for a in node.ancestors.iter() {
-        indented_print(&a.borrow(), indent+1, f);
+    /// Operator symbol for this node's kind, as in noria-server/dataflow/src/ops/<type>::description.
+    pub fn symbol(&self) -> &'static str {
+        match self.data {
+            TestNodeData::Base{..} => "B",
+            TestNodeData::InnerJoin => "⋈",
+            TestNodeData::OuterJoin => "⋉",
+            TestNodeData::Project => "π",
+            TestNodeData::Leaf => "≡",
+            TestNodeData::UnimplementedNode => "?",
+        }
     }
-    write!(f,"")
-}
-
-impl TestNode {
-    pub fn describe(&self) -> String {
-        let mut s = String::new();
 
-        s.push_str(&format!(
-            "[label=\"{} {}\"]\n",
-            self.name,
-            self.maxrows
-        )); // "⋈", "⋉", "π", "⋃" etc. from noria-server/dataflow/src/ops/<type>::description
+    /// Name of the node's kind in exported graphs.
+    pub fn kind(&self) -> &'static str {
+        match self.data {
+            TestNodeData::Base{..} => "base",
+            TestNodeData::InnerJoin => "inner-join",
+            TestNodeData::OuterJoin => "outer-join",
+            TestNodeData::Project => "project",
+            TestNodeData::Leaf => "leaf",
+            TestNodeData::UnimplementedNode => "unimplemented",
+        }
+    }
 
-        s
+    /// Queries whose output this node is: those that use it while none of its children do.
+    pub fn leaf_of<'a>(&'a self, graph: &'a Graph) -> Vec<&'a str> {
+        self.users.iter()
+                  .filter(|u| !self.children.iter().any(|&c| graph[c].users.contains(*u)))
+                  .map(|u| u.as_str())
+                  .collect()
     }
 }
 
@@ -90,7 +206,7 @@ impl PartialEq for TestNode {
 
 
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum TestNodeData {
     Base {
         primary_key: Vec<Column>,
@@ -103,131 +219,453 @@ pub enum TestNodeData {
 }
 
 impl TestNode {
-    pub fn new(
+    pub fn is_base(&self) -> bool {
+        matches!(self.data, TestNodeData::Base{..})
+    }
+
+    /// Estimated state size, assuming every column is `BYTES_PER_COLUMN` wide.
+    pub fn estimated_bytes(&self) -> usize {
+        self.maxrows * self.columns.len() * BYTES_PER_COLUMN
+    }
+
+    pub fn is_join(&self) -> bool {
+        matches!(self.data, TestNodeData::InnerJoin | TestNodeData::OuterJoin)
+    }
+}
+
+
+/// Arena that owns every node of a planned graph. Edges are node indices rather than shared
+/// pointers, so there are no reference cycles and a graph can be sent to another thread.
+#[derive(Clone, Debug, Default)]
+pub struct Graph {
+    nodes: Vec<TestNode>,
+}
+
+impl Graph {
+    pub fn new() -> Self {
+        Graph { nodes: Vec::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, TestNode> {
+        self.nodes.iter()
+    }
+
+    pub fn nodes(&self) -> &[TestNode] {
+        &self.nodes
+    }
+
+    /// Estimated rows held across all non-base nodes.
+    pub fn total_rows(&self) -> usize {
+        self.nodes.iter()
+                  .filter(|node| !node.is_base())
+                  .map(|node| node.maxrows)
+                  .sum()
+    }
+
+    /// Estimated bytes held across all non-base nodes.
+    pub fn total_bytes(&self) -> usize {
+        self.nodes.iter()
+                  .filter(|node| !node.is_base())
+                  .map(|node| node.estimated_bytes())
+                  .sum()
+    }
+
+    /// The columns a node reading `n` sees. A leaf has none of its own; it exposes those of
+    /// the node it reads, as columns of its view.
+    pub fn output_columns(&self, n: NodeIndex) -> Vec<Column> {
+        let node = &self.nodes[n];
+        match (&node.data, node.ancestors.first()) {
+            (&TestNodeData::Leaf, Some(&a)) => self.nodes[a].columns.iter()
+                .map(|c| Column {
+                    name: String::from(c.output_name()),
+                    table: Some(node.name.clone()),
+                    alias: None,
+                    ..c.clone()
+                })
+                .collect(),
+            _ => node.columns.clone(),
+        }
+    }
+
+    /// Adds a node at the end of the graph and registers it as a child of its ancestors.
+    pub fn add_node(
+        &mut self,
         name: &str,
-        index: usize,
         data: TestNodeData,
         columns: Vec<Column>,
-        ancestors: Vec<TestNodeRef>,
-        children: Vec<TestNodeRef>,
+        ancestors: Vec<NodeIndex>,
         maxrows: usize,
-    ) -> TestNodeRef {
-        let mn = TestNode {
+    ) -> NodeIndex {
+        let index = self.nodes.len();
+        for &ancestor in ancestors.iter() {
+            self.nodes[ancestor].children.push(index);
+        }
+        self.nodes.push(TestNode {
             name: String::from(name),
-            index: index,
-            data: data,
-            columns: columns,
-            ancestors: ancestors.clone(),
-            children: children.clone(),
-            maxrows: maxrows,
-        };
+            index,
+            data,
+            columns,
+            ancestors,
+            children: Vec::new(),
+            maxrows,
+            users: BTreeSet::new(),
+            join_keys: Vec::new(),
+            reader_key: Vec::new(),
+        });
+        index
+    }
+
+    /// Adds a node exactly as given, edges included. Used to rebuild saved graphs, whose nodes
+    /// must arrive in index order.
+    pub fn push_node(&mut self, node: TestNode) -> NodeIndex {
+        assert_eq!(node.index, self.nodes.len());
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    /// `node` and everything it depends on, each node once.
+    pub fn ancestor_closure(&self, node: NodeIndex) -> Vec<NodeIndex> {
+        let mut seen = Vec::new();
+        let mut stack = vec![node];
+        while let Some(n) = stack.pop() {
+            if seen.contains(&n) {
+                continue;
+            }
+            stack.extend(self.nodes[n].ancestors.iter().cloned());
+            seen.push(n);
+        }
+        seen
+    }
+
+    /// Deletes `unused` and renumbers the remaining nodes and their edges so that
+    /// `graph[i].index == i` still holds. Returns the new index of every old node, or `None` for
+    /// the deleted ones.
+    pub fn remove_nodes(&mut self, unused: &[NodeIndex]) -> Vec<Option<NodeIndex>> {
+        let mut remap = Vec::with_capacity(self.nodes.len());
+        let mut next = 0;
+        for i in 0..self.nodes.len() {
+            if unused.contains(&i) {
+                remap.push(None);
+            } else {
+                remap.push(Some(next));
+                next += 1;
+            }
+        }
 
-        let rc_mn = Rc::new(RefCell::new(mn));
+        self.nodes.retain(|n| remap[n.index].is_some());
+        for node in self.nodes.iter_mut() {
+            node.index = remap[node.index].unwrap();
+            node.ancestors = node.ancestors.iter().filter_map(|&a| remap[a]).collect();
+            node.children = node.children.iter().filter_map(|&c| remap[c]).collect();
+        }
+        remap
+    }
 
-        // register as child on ancestors
-        for ref ancestor in ancestors {
-            ancestor.borrow_mut().add_child(rc_mn.clone());
+    /// Prints `node` and its ancestors as an indented tree.
+    pub fn indented_print(&self, node: NodeIndex, indent: usize, f: &mut fmt::Formatter) -> fmt::Result {
+        let spaces = (0..indent*3).map(|_| " ").collect::<String>();
+        writeln!(f, "{}-- {}:{:?}", spaces, self[node].name, self[node].data)?;
+        for &a in self[node].ancestors.iter() {
+            self.indented_print(a, indent+1, f)?;
         }
+        Ok(())
+    }
+}
+
+impl Index<NodeIndex> for Graph {
+    type Output = TestNode;
 
-        rc_mn
+    fn index(&self, i: NodeIndex) -> &TestNode {
+        &self.nodes[i]
     }
+}
 
-    pub fn add_child(&mut self, c: TestNodeRef) {
-        self.children.push(c)
+impl IndexMut<NodeIndex> for Graph {
+    fn index_mut(&mut self, i: NodeIndex) -> &mut TestNode {
+        &mut self.nodes[i]
     }
 }
 
-pub fn get_empty_node() -> TestNodeRef {
-    TestNode::new(
-        "unimplemented",
-        0,
-        TestNodeData::UnimplementedNode,
-        Vec::new(),
-        Vec::new(),
-        Vec::new(),
-        0,
-    )
+impl<'a> IntoIterator for &'a Graph {
+    type Item = &'a TestNode;
+    type IntoIter = slice::Iter<'a, TestNode>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.nodes.iter()
+    }
 }
 
 
-pub fn parse_queries(queries: Vec<String>, opts: Optimizations, outf: Option<&Path>) -> (i32, i32) {
-    let mut parsed_ok = Vec::new();
-    let mut parsed_err = 0;
+/// Plans every statement in `queries` into a fresh graph without printing anything.
+pub fn plan_queries(queries: &[String], opts: &Optimizations) -> PlanReport {
+    extend_queries(Graph::new(), HashMap::new(), queries, opts)
+}
 
-    let mut graph = Vec::new(); //Vec<TestNodeRef>
-    let mut tables = HashMap::new();  // map <name:String, basenode:TestNodeRef>
+/// Plans `queries` on top of an existing graph and table map, e.g. one returned by an earlier
+/// `PlanReport` or loaded with `SavedGraph::to_graph`. The report's `first_new` marks where the
+/// nodes added by this migration begin.
+pub fn extend_queries(graph: Graph, tables: HashMap<String, NodeIndex>,
+                      queries: &[String], opts: &Optimizations) -> PlanReport {
+    extend_queries_observed(graph, tables, queries, opts, &mut |_| ())
+}
+
+/// Like `extend_queries`, but calls `observe` with the graph after every statement that parses.
+pub fn extend_queries_observed(graph: Graph, tables: HashMap<String, NodeIndex>, queries: &[String],
+                               opts: &Optimizations, observe: &mut dyn FnMut(&Step)) -> PlanReport {
+    let mut results = Vec::new();
+    let mut failures = Vec::new();
+    let mut errors = Vec::new();
+    let mut parsed_ok = 0;
+
+    let mut graph = graph;
+    let mut tables = tables;  // map <name:String, basenode:NodeIndex>
+    let mut migration_start = graph.len();
 
-    for query in queries.iter() {
-        match nom_sql::parser::parse_query(&query) {
+    let mut removals: Vec<Removal> = Vec::new();
+
+    for (position, query) in queries.iter().enumerate() {
+        // nom-sql has no DROP VIEW, so pick those out before parsing
+        if let Some(names) = parse_drop_view(query) {
+            parsed_ok += 1;
+            for name in names {
+                if let Some(removal) = remove_query(&name, &mut graph, &mut tables) {
+                    migration_start = removal.apply(&mut results, migration_start);
+                    removals.push(removal);
+                }
+            }
+            observe(&Step { sql: query, query: None, new_nodes: Vec::new(), graph: &graph });
+            continue;
+        }
+        match nom_sql::parser::parse_query(query) {
             Ok(q) => {
-                //println!("ok");
-                parsed_ok.push(query);
-                match q {
+                parsed_ok += 1;
+                let first_new = graph.len();
+                let mut at = Location { name: view_name(query), position, line: None };
+                let planned = match q {
                     SqlQuery::Select(ref select) => {
-                        make_select(select, &tables, &mut graph, opts.clone());
+                        // a SELECT without a name from the workload file is a view like any other
+                        let name = anonymous_name(position, &tables);
+                        at.name = Some(name.clone());
+                        make_select(select, &tables, &mut graph, opts.clone(), &at).map(|(select_node, key)| {
+                            let leaf = make_leaf(&name, select_node, key, &mut graph);
+                            tables.insert(name.clone(), leaf);
+                            Some((name, leaf))
+                        })
+                    },
+                    SqlQuery::CompoundSelect(_) => {
+                        at.name = Some(anonymous_name(position, &tables));
+                        Err(PlanError::CompoundSelect { at })
                     },
-                    SqlQuery::Insert(ref _insert) => (),
+                    SqlQuery::Insert(ref _insert) => Ok(None),
                     SqlQuery::CreateTable(ref create) => {
-                        make_table(create, &mut tables, &mut graph);
+                        make_table(create, &mut tables, &mut graph, &opts.statistics);
+                        Ok(None)
                     },
                     SqlQuery::CreateView(ref create) => {
-                        let (t, view) = make_view(create, &tables, &mut graph, opts.clone());
-                        tables.insert(t, view);
+                        make_view(create, &tables, &mut graph, opts.clone(), &at).map(|(t, view)| {
+                            tables.insert(t.clone(), view);
+                            Some((t, view))
+                        })
+                    },
+                    SqlQuery::Delete(ref _delete) => Ok(None),
+                    SqlQuery::DropTable(ref drop) => {
+                        for table in drop.tables.iter() {
+                            for removal in drop_table(&table.name, &mut graph, &mut tables) {
+                                migration_start = removal.apply(&mut results, migration_start);
+                                removals.push(removal);
+                            }
+                        }
+                        Ok(None)
                     },
-                    SqlQuery::Delete(ref _delete) => (),
-                    SqlQuery::DropTable(ref _drop) => (),
-                    SqlQuery::Update(ref _update) => (),
-                    SqlQuery::Set(ref _set) => (),
-                    _ => unimplemented!(),
+                    SqlQuery::Update(ref _update) => Ok(None),
+                    SqlQuery::Set(ref _set) => Ok(None),
+                };
+                let planned = match planned {
+                    Ok(planned) => planned,
+                    Err(e) => {
+                        // the statement is skipped and the graph left as it was
+                        errors.push(e);
+                        observe(&Step { sql: query, query: None, new_nodes: Vec::new(), graph: &graph });
+                        continue;
+                    }
+                };
+                if let Some((name, leaf)) = planned {
+                    for node in graph.ancestor_closure(leaf) {
+                        graph[node].users.insert(name.clone());
+                    }
+                    results.push(QueryResult {
+                        name,
+                        sql: query.clone(),
+                        leaf,
+                        new_nodes: (first_new..graph.len()).collect(),
+                        reused_nodes: reused_nodes(&graph, leaf, first_new),
+                    });
+                    let result = results.last().unwrap();
+                    observe(&Step { sql: query, query: Some(result), new_nodes: result.new_nodes.clone(), graph: &graph });
+                } else {
+                    // tables add their base node; drops remove nodes and add none
+                    let new_nodes = (first_new.min(graph.len())..graph.len()).collect();
+                    observe(&Step { sql: query, query: None, new_nodes, graph: &graph });
                 }
             }
-            Err(_) => {
-                println!("failed to parse '{}'", &query);
-                parsed_err += 1;
-            }
+            Err(e) => failures.push(ParseFailure {
+                name: view_name(query),
+                sql: query.clone(),
+                error: e.to_string(),
+                position,
+                line: None,
+            }),
         }
     }
-    let njoins = graph.iter()
-                      .filter(|node| match node.borrow().data {
-                          TestNodeData::InnerJoin => true,
-                          TestNodeData::OuterJoin => true,
-                          _ => false,
-                      })
-                      .count();
-    println!("NUM_NODES: {}\nNUM_JOINS: {}", graph.len(), njoins);
+
+    PlanReport { queries: results, failures, errors, removals, parsed_ok, first_new: migration_start, graph, tables }
+}
+
+/// Name for the SELECT at `position` in a batch: `query_<n>`, counting from one, with a suffix
+/// if a relation of that name already exists.
+fn anonymous_name(position: usize, tables: &HashMap<String, NodeIndex>) -> String {
+    let name = format!("query_{}", position + 1);
+    let mut unique = name.clone();
+    let mut suffix = 2;
+    while tables.contains_key(&unique) {
+        unique = format!("{}_{}", name, suffix);
+        suffix += 1;
+    }
+    unique
+}
+
+/// The name in a `CREATE VIEW name AS ...` statement, read without parsing the rest, so that
+/// statements nom-sql rejects can still be reported by name.
+pub fn view_name(query: &str) -> Option<String> {
+    let words: Vec<&str> = query.split_whitespace().take(4).collect();
+    if words.len() == 4 && words[0].eq_ignore_ascii_case("create") && words[1].eq_ignore_ascii_case("view")
+            && words[3].eq_ignore_ascii_case("as") {
+        Some(words[2].trim_matches('`').to_string())
+    } else {
+        None
+    }
+}
+
+/// Recognizes `DROP VIEW [IF EXISTS] a, b` and returns the view names.
+pub fn parse_drop_view(query: &str) -> Option<Vec<String>> {
+    let words: Vec<&str> = query.trim().trim_end_matches(';').split_whitespace().collect();
+    if words.len() < 3 || !words[0].eq_ignore_ascii_case("drop") || !words[1].eq_ignore_ascii_case("view") {
+        return None;
+    }
+    let mut rest = &words[2..];
+    if rest.len() > 2 && rest[0].eq_ignore_ascii_case("if") && rest[1].eq_ignore_ascii_case("exists") {
+        rest = &rest[2..];
+    }
+    Some(rest.join(" ")
+             .split(',')
+             .map(|name| name.trim().trim_matches('`').to_string())
+             .filter(|name| !name.is_empty())
+             .collect())
+}
+
+/// Removes the query or view `name` and frees every non-base node that no remaining query
+/// uses. Returns `None` if nothing in the graph belongs to `name`.
+pub fn remove_query(name: &str, graph: &mut Graph, tables: &mut HashMap<String, NodeIndex>) -> Option<Removal> {
+    let mut found = false;
+    for node in graph.nodes.iter_mut() {
+        found |= node.users.remove(name);
+    }
+    if tables.get(name).is_some_and(|&n| !graph[n].is_base()) {
+        tables.remove(name);
+        found = true;
+    }
+    if !found {
+        return None;
+    }
+    let unused: Vec<NodeIndex> = graph.iter()
+                                      .filter(|n| !n.is_base() && n.users.is_empty())
+                                      .map(|n| n.index)
+                                      .collect();
+    Some(free_nodes(name, &unused, graph, tables))
+}
+
+/// Drops `name` from the schema. Views are removed like `remove_query`; base tables are
+/// removed along with every query that reads them, like `DROP TABLE ... CASCADE`.
+pub fn drop_table(name: &str, graph: &mut Graph, tables: &mut HashMap<String, NodeIndex>) -> Vec<Removal> {
+    match tables.get(name) {
+        None => return Vec::new(),
+        Some(&node) if !graph[node].is_base() => return remove_query(name, graph, tables).into_iter().collect(),
+        Some(_) => (),
+    }
+    let users: Vec<String> = graph[tables[name]].users.iter().cloned().collect();
+    let mut removals: Vec<Removal> = users.iter()
+                                          .filter_map(|user| remove_query(user, graph, tables))
+                                          .collect();
+    let base = tables.remove(name).unwrap();
+    removals.push(free_nodes(name, &[base], graph, tables));
+    removals
+}
+
+/// Deletes `unused` from the graph and points `tables` at the renumbered nodes.
+fn free_nodes(name: &str, unused: &[NodeIndex], graph: &mut Graph, tables: &mut HashMap<String, NodeIndex>) -> Removal {
+    let freed_nodes = unused.iter().map(|&n| format!("n{} {}", n, graph[n].name)).collect();
+    let freed_rows = unused.iter().map(|&n| graph[n].maxrows).sum();
+    let freed_bytes = unused.iter().map(|&n| graph[n].estimated_bytes()).sum();
+    let remap = graph.remove_nodes(unused);
+    for node in tables.values_mut() {
+        *node = remap[*node].unwrap();
+    }
+    Removal { name: String::from(name), freed_nodes, freed_rows, freed_bytes, remap }
+}
+
+/// The distinct non-base nodes that `node` depends on and that already existed before index
+/// `first_new`, i.e. the nodes a query reused rather than created.
+pub fn reused_nodes(graph: &Graph, node: NodeIndex, first_new: usize) -> Vec<NodeIndex> {
+    let mut reused: Vec<NodeIndex> = graph.ancestor_closure(node)
+                                          .into_iter()
+                                          .filter(|&n| !graph[n].is_base() && n < first_new)
+                                          .collect();
+    reused.sort();
+    reused
+}
+
+/// Plans `queries`, starting from `start` if given, then prints the report and writes the graph.
+/// With `steps`, also writes a snapshot of the graph after every statement. A graph that cannot
+/// be written is reported as an error without a location.
+pub fn parse_queries(queries: Vec<String>, opts: Optimizations, outf: Option<&Path>,
+                     start: Option<(Graph, HashMap<String, NodeIndex>)>, steps: Option<&mut StepWriter>) -> PlanReport {
+    let (graph, tables) = start.unwrap_or_default();
+    let mut report = match steps {
+        None => extend_queries(graph, tables, &queries, &opts),
+        Some(writer) => extend_queries_observed(graph, tables, &queries, &opts, &mut |step| writer.write(step)),
+    };
+    print!("{}", report);
+    if report.first_new > 0 {
+        print!("{}", report.migration_plan());
+    }
 
     match outf {
         None => {
-            println!("GRAPHVIZ:\n{}", graphviz(&graph));
+            println!("GRAPHVIZ:\n{}", graphviz(&report.graph));
         }
-        Some(f) => {
-            let mut f = File::create(f).unwrap();
-            f.write_all(format!("{}", graphviz(&graph)).as_bytes());
+        Some(path) => {
+            let written = File::create(path).and_then(|mut f| f.write_all(graphviz(&report.graph).as_bytes()));
+            if let Err(e) = written {
+                report.errors.push(PlanError::Write { path: path.display().to_string(), error: e.to_string() });
+            }
         }
     }
 
-
-    (parsed_ok.len() as i32, parsed_err)
+    report
 }
 
-pub fn make_table(s: &CreateTableStatement, tables: &mut HashMap<String, TestNodeRef>, graph: &mut Vec<TestNodeRef>) -> () {
-    let hardcode_rows: HashMap<&str, usize> =
-        [("customer", 2880000),
-         ("orders", 2592000),
-         ("order_line", 7775551),
-         ("item", 10000),
-         ("author", 625),
-         ("cc_xacts", 2592000),
-         ("country", 92),
-         ("address", 5760000)]
-        .iter().cloned().collect();
+pub fn make_table(s: &CreateTableStatement, tables: &mut HashMap<String, NodeIndex>, graph: &mut Graph,
+                  stats: &Statistics) {
     let t: String = s.table.name.clone();
-    let fields = s.fields.clone()
-                  .into_iter()
-                  .map(|column_spec| Column {
-                      name: column_spec.column.name.clone()
-                  })
-                  .collect();
+    let mut fields: Vec<Column> = s.fields.iter().map(|spec| Column::base(&t, spec)).collect();
 
     let primary_keys: Vec<Column> = match s.keys {
         None => Vec::new(),
@@ -235,118 +673,406 @@ pub fn make_table(s: &CreateTableStatement, tables: &mut HashMap<String, TestNod
             // we're going to assume there's just one primary key and not handle anything else
             let mut keys = Vec::new();
             for table_key in table_keys {
-                match table_key {
-                    TableKey::PrimaryKey(pks) => {
-                        for key in pks {
-                            let pk = Column { name: key.name.clone() };
-                            keys.push(pk);
+                if let TableKey::PrimaryKey(pks) = table_key {
+                    for key in pks {
+                        match fields.iter_mut().find(|f| f.name == key.name) {
+                            Some(field) => {
+                                field.nullable = false;
+                                keys.push(field.clone());
+                            }
+                            None => keys.push(Column::new(&key.name)),
                         }
-                    },
-                    _ => (),
+                    }
                 }
             }
             keys
         },
     };
-    println!("{} has keys {:?}", &t.clone(), &primary_keys.clone());
-
-    let base = TestNode::new(
+    let base = graph.add_node(
         &t.clone(),
-        graph.len(),
         TestNodeData::Base{
             primary_key: primary_keys
         },
         fields,
         Vec::new(),
-        Vec::new(),
-        *hardcode_rows.get::<str>(&t.to_string()).unwrap_or(&10),
+        stats.rows_for(&t),
     );
-    graph.push(base.clone());
     tables.insert(t, base);
 }
 
-pub fn make_select(s: &SelectStatement, tables: &HashMap<String, TestNodeRef>, graph: &mut Vec<TestNodeRef>, opts: Optimizations) -> TestNodeRef {
-    println!("making select for: {}", s);
-    // joins
+/// The relations `s` joins, in order, after checking that the planner can handle the query on
+/// top of `graph`. Nothing is added to the graph until this has passed.
+fn check_select(s: &SelectStatement, tables: &HashMap<String, NodeIndex>, graph: &Graph,
+                at: &Location) -> Result<Vec<String>, PlanError> {
     let mut joinable_names: Vec<String> = s.tables.iter()
                                            .map(|t| t.name.clone())
                                            .collect();
-    let mut more_joinables: Vec<String> = s.join.iter()
-                                           .map(|j| {
-                                               match j.right {
-                                                   JoinRightSide::Table(ref t) => t.name.clone(),
-                                                   _ => unimplemented!(),
-                                               }
-                                           })
-                                           .collect();
-    joinable_names.append(&mut more_joinables);
-    if opts.sorted_names {
-        joinable_names.sort();
-    }
-    joinable_names.dedup();
-
-    let join_result = if opts.megajoin {
-        join::make_combined_joins(joinable_names, tables, graph, opts)
-    } else if opts.permutations {
-        join::make_joins_with_permutations(joinable_names, tables, graph, opts)
-    } else if opts.nonprefix {
-        join::make_joins_nonprefix_overlap(joinable_names, tables, graph, opts)
+    for j in s.join.iter() {
+        match j.right {
+            JoinRightSide::Table(ref t) => joinable_names.push(t.name.clone()),
+            _ => return Err(PlanError::NestedJoin { at: at.clone() }),
+        }
+    }
+    // joins are planned per table, so two instances of one table cannot be told apart
+    let mut seen = BTreeSet::new();
+    if let Some(table) = joinable_names.iter().find(|&name| !seen.insert(name)) {
+        return Err(PlanError::SelfJoin { at: at.clone(), table: table.clone() });
+    }
+    if joinable_names.is_empty() {
+        return Err(PlanError::EmptyFrom { at: at.clone() });
+    }
+    for table in joinable_names.iter() {
+        if !tables.contains_key(table) {
+            return Err(PlanError::UnknownTable { at: at.clone(), table: table.clone() });
+        }
+    }
+    let relations: Vec<NodeIndex> = joinable_names.iter().map(|name| tables[name]).collect();
+    let reusable = |join: &TestNode| sources(join.index, graph).iter().all(|s| relations.contains(s));
+    if let Some(join) = graph.iter().find(|n| n.is_join() && n.ancestors.len() != 2 && reusable(n)) {
+        return Err(PlanError::NonBinaryJoin { at: at.clone(), node: join.index });
+    }
+    Ok(joinable_names)
+}
+
+/// The base tables and views `node` reads, not looking behind views.
+fn sources(node: NodeIndex, graph: &Graph) -> Vec<NodeIndex> {
+    let mut sources = Vec::new();
+    let mut stack = vec![node];
+    while let Some(n) = stack.pop() {
+        if graph[n].is_base() || (n != node && graph[n].data == TestNodeData::Leaf) {
+            sources.push(n);
+        } else {
+            stack.extend(graph[n].ancestors.iter().cloned());
+        }
+    }
+    sources
+}
+
+/// Plans `s` and returns the node producing its result, along with the key of its reader.
+pub fn make_select(s: &SelectStatement, tables: &HashMap<String, NodeIndex>, graph: &mut Graph, opts: Optimizations,
+                   at: &Location) -> Result<(NodeIndex, Vec<Column>), PlanError> {
+    let strategy_name = opts.strategy_name();
+    let strategy = strategy::get_strategy(&strategy_name)
+        .ok_or_else(|| PlanError::UnknownStrategy { at: at.clone(), strategy: strategy_name.clone() })?;
+    let joinable_names = check_select(s, tables, graph, at)?;
+    let resolution = resolve_select(s, tables, graph, at)?;
+
+    // joins, reading base tables through projections of the columns the query needs
+    let all = s.fields.contains(&FieldDefinitionExpression::All);
+    let starred: Vec<String> = s.fields.iter().filter_map(|f| match *f {
+        FieldDefinitionExpression::AllInTable(ref table) => Some(table.clone()),
+        _ => None,
+    }).collect();
+    let needed = prune::needed_columns(&resolution, all, &starred);
+    let predicates = resolution.qualify_predicates(&strategy::join_predicates(s));
+    let query = JoinQuery { select: s, relations: &joinable_names, predicates: &predicates, needed: &needed };
+    let inlined = if all || !starred.is_empty() {
+        // the query's columns are the views' own
+        Vec::new()
     } else {
-        join::make_all_joins(joinable_names, tables, graph, opts)
+        inline_views(&query, &resolution, tables, graph, strategy.as_ref(), &opts)
     };
+    let join_result = plan_joins(&query, &inlined, tables, graph, strategy.as_ref(), &opts);
 
     // projection
     let mut columns_to_project = Vec::new();
     for field in s.fields.iter() {
         match field {
             FieldDefinitionExpression::All => {
-                let mut columns = join_result.borrow().columns.clone();
+                let mut columns = graph.output_columns(join_result);
                 columns_to_project.append(&mut columns);
             }
             FieldDefinitionExpression::AllInTable(ref table) => {
-                let mut columns = tables.get(table).unwrap().borrow().columns.clone();
-                columns_to_project.append(&mut columns);
+                // `alias.*` reads the table the alias stands for
+                let relation = resolution.relation(table)
+                                         .or_else(|| resolution.relations.iter().find(|r| r.table == *table))
+                                         .expect("resolve_select checks starred tables");
+                columns_to_project.extend(relation.columns.iter().cloned());
+            }
+            FieldDefinitionExpression::Value(FieldValueExpression::Arithmetic(ref a)) => {
+                columns_to_project.push(arithmetic_column(a, &resolution));
             }
-            FieldDefinitionExpression::Value(ref val) => println!("value: {}", val), // TODO
-            FieldDefinitionExpression::Col(ref col) => {
-                let column = Column{ name: col.name.clone() };  // TODO this might include expressions
+            FieldDefinitionExpression::Value(FieldValueExpression::Literal(ref l)) => {
+                let mut column = Column::computed(&l.value.to_string(), None, l.value == Literal::Null, None);
+                column.alias = l.alias.clone();
                 columns_to_project.push(column);
             }
-            _ => unimplemented!(),
+            FieldDefinitionExpression::Col(ref col) => columns_to_project.push(project_column(col, &resolution)),
         }
     }
-    let maxrows = (&join_result).borrow().maxrows.clone();
-    let projection = TestNode::new(
+    let maxrows = graph[join_result].maxrows;
+    let project = graph.add_node(
         "project",
-        graph.len(),
         TestNodeData::Project,
         columns_to_project,
         vec![join_result], // ancestors
-        Vec::new(), // children
         maxrows,
     );
-    graph.push(projection.clone());
-    projection
+    Ok((project, reader_key(s, &resolution)))
 }
 
-pub fn make_view(s: &CreateViewStatement, tables: &HashMap<String, TestNodeRef>, graph: &mut Vec<TestNodeRef>, opts: Optimizations) -> (String, TestNodeRef) {
+/// What the join part of a query needs planned: the relations it joins, the predicates it
+/// joins them on and the columns it reads.
+struct JoinQuery<'a> {
+    select: &'a SelectStatement,
+    relations: &'a [String],
+    predicates: &'a [JoinPredicate],
+    needed: &'a [ResolvedColumn],
+}
+
+/// Plans the joins of `query` with the views of `inlined` taken apart, reading base tables
+/// through projections of the columns the query needs. Returns the node the query's projection
+/// reads.
+fn plan_joins(query: &JoinQuery, inlined: &[InlinedView], tables: &HashMap<String, NodeIndex>, graph: &mut Graph,
+              strategy: &dyn JoinStrategy, opts: &Optimizations) -> NodeIndex {
+    let relations = inline::relations(query.relations, inlined);
+    let predicates = inline::predicates(query.predicates, inlined);
+    let needed = inline::needed(query.needed, inlined);
+    let inputs = prune::narrow_inputs(&relations, &needed, tables, graph);
+    // strategies know the relations by table, so they get the predicates the same way
+    let aliases = table_aliases(query.select);
+    let by_table: Vec<JoinPredicate> = predicates.iter()
+        .map(|p| JoinPredicate {
+            left: nom_sql::Column { table: column_relation(&p.left, &aliases), ..p.left.clone() },
+            right: nom_sql::Column { table: column_relation(&p.right, &aliases), ..p.right.clone() },
+        })
+        .collect();
+    let first_new = graph.len();
+    let join_result = strategy.plan(&relations, &by_table, &inputs, graph, opts);
+    add_join_keys(query.select, &predicates, tables, graph, first_new);
+    prune::prune_joins(join_result, &needed, first_new, graph);
+    join_result
+}
+
+/// The views of `query` to take apart rather than read through their leaves. A view is taken
+/// apart when that makes the graph cheaper under the cost model, typically because its joins
+/// can then be shared with the rest of the query; ties keep the leaf. Views are considered in
+/// the order the query reads them, each on top of the ones already taken apart, and a view is
+/// kept whole if it reads a table the query also reads some other way.
+fn inline_views(query: &JoinQuery, resolution: &Resolution, tables: &HashMap<String, NodeIndex>, graph: &Graph,
+                strategy: &dyn JoinStrategy, opts: &Optimizations) -> Vec<InlinedView> {
+    let cost = |inlined: &[InlinedView]| {
+        let mut alternative = graph.clone();
+        plan_joins(query, inlined, tables, &mut alternative, strategy, opts);
+        opts.cost.graph_cost(&alternative)
+    };
+    let mut inlined: Vec<InlinedView> = Vec::new();
+    // the cost with the views accepted so far, once there is a view to compare it to
+    let mut current: Option<f64> = None;
+    for relation in resolution.relations.iter() {
+        if resolution.relations.iter().filter(|r| r.table == relation.table).count() > 1 {
+            continue;
+        }
+        let view = match inline::inline_view(&relation.table, &relation.name, tables, graph) {
+            Some(view) => view,
+            None => continue,
+        };
+        let read = inline::relations(query.relations, &inlined);
+        if view.tables.iter().any(|t| read.contains(t)) {
+            continue;
+        }
+        let baseline = *current.get_or_insert_with(|| cost(&inlined));
+        let mut candidate = inlined.clone();
+        candidate.push(view);
+        let candidate_cost = cost(&candidate);
+        if candidate_cost < baseline {
+            inlined = candidate;
+            current = Some(candidate_cost);
+        }
+    }
+    inlined
+}
+
+/// The output column for `col` of the select list: the column it resolved to, or for an
+/// aggregate a computed column derived from its argument.
+fn project_column(col: &nom_sql::Column, resolution: &Resolution) -> Column {
+    let source = |c: &nom_sql::Column| resolution.get(c).and_then(|r| resolution.column(r)).cloned();
+    let mut column = match col.function {
+        None => source(col).unwrap_or_else(|| Column::new(&col.name)),
+        Some(ref function) => {
+            // counts are never NULL; the other aggregates are NULL over no rows
+            let (argument, counts) = match **function {
+                FunctionExpression::CountStar => (None, true),
+                FunctionExpression::Count(ref c, _) => (source(c), true),
+                FunctionExpression::Avg(ref c, _) |
+                FunctionExpression::Sum(ref c, _) |
+                FunctionExpression::Max(ref c) |
+                FunctionExpression::Min(ref c) |
+                FunctionExpression::GroupConcat(ref c, _) => (source(c), false),
+            };
+            let sql_type = match **function {
+                FunctionExpression::Max(_) | FunctionExpression::Min(_) => argument.as_ref().and_then(|a| a.sql_type.clone()),
+                _ => None,
+            };
+            Column::computed(&col.name, sql_type, !counts, argument.as_ref())
+        }
+    };
+    column.alias = col.alias.clone().filter(|alias| *alias != column.name);
+    column
+}
+
+/// The output column for an arithmetic expression of the select list, computed from the
+/// columns among its operands. It is NULL whenever an operand is.
+fn arithmetic_column(a: &ArithmeticExpression, resolution: &Resolution) -> Column {
+    let operands: Vec<Option<&Column>> = [&a.left, &a.right].iter()
+        .map(|operand| match **operand {
+            ArithmeticBase::Column(ref c) => resolution.get(c).and_then(|r| resolution.column(r)),
+            ArithmeticBase::Scalar(_) => None,
+        })
+        .collect();
+    let null_scalar = [&a.left, &a.right].iter().any(|o| **o == ArithmeticBase::Scalar(Literal::Null));
+    let nullable = null_scalar || operands.iter().any(|c| c.is_some_and(|c| c.nullable));
+    let name = format!("{} {} {}", a.left, a.op, a.right);
+    let mut column = Column::computed(&name, None, nullable, operands.into_iter().flatten());
+    column.alias = a.alias.clone();
+    column
+}
+
+/// The tables `s` reads under an alias, by alias.
+fn table_aliases(s: &SelectStatement) -> HashMap<String, String> {
+    s.tables.iter()
+     .chain(s.join.iter().filter_map(|j| match j.right {
+         JoinRightSide::Table(ref t) => Some(t),
+         _ => None,
+     }))
+     .filter_map(|t| t.alias.clone().map(|alias| (alias, t.name.clone())))
+     .collect()
+}
+
+/// The table `column` belongs to, resolving aliases. Unqualified columns name output aliases,
+/// which belong to no table.
+fn column_relation(column: &nom_sql::Column, aliases: &HashMap<String, String>) -> Option<String> {
+    let table = column.table.as_ref()?;
+    Some(aliases.get(table).cloned().unwrap_or_else(|| table.clone()))
+}
+
+/// Records on every join added since `first_new` which of `predicates` connect its two inputs.
+fn add_join_keys(s: &SelectStatement, predicates: &[JoinPredicate],
+                 tables: &HashMap<String, NodeIndex>, graph: &mut Graph, first_new: NodeIndex) {
+    let aliases = table_aliases(s);
+    let resolved: Vec<(&JoinPredicate, String, String)> = predicates.iter()
+        .filter_map(|p| {
+            let left = column_relation(&p.left, &aliases)?;
+            let right = column_relation(&p.right, &aliases)?;
+            Some((p, left, right))
+        })
+        .collect();
+    for node in first_new..graph.len() {
+        if !graph[node].is_join() || graph[node].ancestors.len() != 2 {
+            continue;
+        }
+        let sides: Vec<Vec<NodeIndex>> = graph[node].ancestors.iter().map(|&a| graph.ancestor_closure(a)).collect();
+        let side_of = |relation: &str| {
+            let base = tables.get(relation)?;
+            sides.iter().position(|side| side.contains(base))
+        };
+        // each key with the column it reads on the first and on the second side, once however
+        // the query wrote it
+        let mut keys: Vec<(&JoinPredicate, KeyColumn, KeyColumn)> = Vec::new();
+        for &(p, ref l, ref r) in resolved.iter() {
+            let key = match (side_of(l), side_of(r)) {
+                (Some(0), Some(1)) => (p, (l.as_str(), p.left.name.as_str()), (r.as_str(), p.right.name.as_str())),
+                (Some(1), Some(0)) => (p, (r.as_str(), p.right.name.as_str()), (l.as_str(), p.left.name.as_str())),
+                _ => continue,
+            };
+            if !keys.iter().any(|&(k, _, _)| k.same_as(p)) {
+                keys.push(key);
+            }
+        }
+        let columns = join_columns(&graph[node], &keys, graph);
+        graph[node].join_keys = keys.iter().map(|&(p, _, _)| p.clone()).collect();
+        graph[node].columns = columns;
+    }
+}
+
+/// A join key column as (table, column).
+type KeyColumn<'a> = (&'a str, &'a str);
+
+/// The columns of `join` given its `keys`: its first input's followed by its second's. An
+/// inner join outputs a key column of the same name only once, from the first input, and no
+/// key column is NULL in its output. An outer join keeps both, with the second's nullable.
+fn join_columns(join: &TestNode, keys: &[(&JoinPredicate, KeyColumn, KeyColumn)], graph: &Graph) -> Vec<Column> {
+    let is = |c: &Column, &(table, name): &(&str, &str)| c.table.as_ref().is_some_and(|t| t == table) && c.name == name;
+    let mut first = graph.output_columns(join.ancestors[0]);
+    let mut second = graph.output_columns(join.ancestors[1]);
+    if join.data == TestNodeData::OuterJoin {
+        second.iter_mut().for_each(|c| c.nullable = true);
+        first.append(&mut second);
+        return first;
+    }
+    for (_, left, right) in keys.iter() {
+        let right_column = second.iter().position(|c| is(c, right));
+        if let Some(l) = first.iter_mut().find(|c| is(c, left)) {
+            l.nullable = false;
+            if let Some(r) = right_column {
+                if second[r].name == l.name {
+                    let merged = second.remove(r);
+                    l.lineage.extend(merged.lineage);
+                    l.lineage.sort();
+                    l.lineage.dedup();
+                }
+            }
+        }
+        if let Some(r) = second.iter_mut().find(|c| is(c, right)) {
+            r.nullable = false;
+        }
+    }
+    first.append(&mut second);
+    first
+}
+
+pub fn make_view(s: &CreateViewStatement, tables: &HashMap<String, NodeIndex>, graph: &mut Graph, opts: Optimizations,
+                 at: &Location) -> Result<(String, NodeIndex), PlanError> {
     match *(s.clone().definition) {
-        SelectSpecification::Compound(_) => unimplemented!(),
+        SelectSpecification::Compound(_) => Err(PlanError::CompoundSelect { at: at.clone() }),
         SelectSpecification::Simple(ss) => {
-            let select_node = make_select(&ss, tables, graph, opts);
-            let maxrows = (&select_node).borrow().maxrows.clone();
-            let view = TestNode::new(
-                &s.name.clone(),
-                graph.len(),
-                TestNodeData::Leaf,
-                Vec::new(),
-                vec![select_node],
-                Vec::new(),
-                maxrows,
-            );
-            //println!("view:\n{}", view.borrow());
-            graph.push(view.clone());
-            (s.name.clone(), view)
+            let (select_node, key) = make_select(&ss, tables, graph, opts, at)?;
+            Ok((s.name.clone(), make_leaf(&s.name, select_node, key, graph)))
+        }
+    }
+}
+
+/// Adds the `Leaf` node through which the query or view `name` reads `select_node`, keyed on
+/// `key`.
+pub fn make_leaf(name: &str, select_node: NodeIndex, key: Vec<Column>, graph: &mut Graph) -> NodeIndex {
+    let maxrows = graph[select_node].maxrows;
+    let leaf = graph.add_node(name, TestNodeData::Leaf, Vec::new(), vec![select_node], maxrows);
+    graph[leaf].reader_key = key;
+    leaf
+}
+
+/// The columns compared to a `?` parameter in the WHERE clause, which Noria turns into the key
+/// of the query's reader. Each is the column of the relation it resolved to.
+pub fn reader_key(s: &SelectStatement, resolution: &Resolution) -> Vec<Column> {
+    let mut key = Vec::new();
+    if let Some(ref cond) = s.where_clause {
+        collect_parameters(cond, resolution, &mut key);
+    }
+    key
+}
+
+fn collect_parameters(cond: &ConditionExpression, resolution: &Resolution, key: &mut Vec<Column>) {
+    match *cond {
+        ConditionExpression::LogicalOp(ref tree) => {
+            collect_parameters(&tree.left, resolution, key);
+            collect_parameters(&tree.right, resolution, key);
+        }
+        ConditionExpression::ComparisonOp(ref tree) if tree.operator == Operator::Equal => {
+            let column = match (&*tree.left, &*tree.right) {
+                (&ConditionExpression::Base(ConditionBase::Field(ref c)),
+                 &ConditionExpression::Base(ConditionBase::Literal(Literal::Placeholder))) |
+                (&ConditionExpression::Base(ConditionBase::Literal(Literal::Placeholder)),
+                 &ConditionExpression::Base(ConditionBase::Field(ref c))) => {
+                    // output aliases belong to no relation
+                    resolution.get(c).and_then(|r| resolution.column(r)).cloned().unwrap_or_else(|| Column::new(&c.name))
+                }
+                _ => return,
+            };
+            if !key.contains(&column) {
+                key.push(column);
+            }
         }
+        ConditionExpression::Bracketed(ref inner) => collect_parameters(inner, resolution, key),
+        _ => (),
     }
 }
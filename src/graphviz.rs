@@ -2,45 +2,371 @@ extern crate nom_sql;
 
 
 
-use graph::TestNodeRef;
+use graph::{Graph, NodeIndex, TestNode, TestNodeData};
 
+use std::collections::{BTreeMap, BTreeSet};
 
-pub fn graphviz(graph: &Vec<TestNodeRef>) -> String {
-    let mut s = String::new();
 
-    let indentln = |s: &mut String| s.push_str("    ");
+const COLOR: &str = "#0C6fA9";
+pub const NEW_COLOR: &str = "#D62728";
+pub const REUSED_COLOR: &str = "#2CA02C";
+/// Columns listed on a projection's label before the rest are summarized.
+pub const MAX_LABEL_COLUMNS: usize = 4;
+/// Length query names are cut to on leaf labels.
+pub const MAX_QUERY_NAME: usize = 40;
+
+/// Layout choices for `graphviz_with`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DotOptions {
+    /// Group each query's nodes in a cluster of their own. Base tables stay outside, and a
+    /// shared node goes in the cluster of the first query that uses it.
+    pub clustered: bool,
+    /// Nodes drawn in `NEW_COLOR`, e.g. the ones the latest statement added.
+    pub new_nodes: BTreeSet<NodeIndex>,
+    /// Nodes drawn in `REUSED_COLOR`, e.g. existing ones the latest statement depends on.
+    pub reused_nodes: BTreeSet<NodeIndex>,
+}
+
+impl DotOptions {
+    /// Border color for `node`, if it is highlighted.
+    pub fn highlight(&self, node: NodeIndex) -> Option<&'static str> {
+        if self.new_nodes.contains(&node) {
+            Some(NEW_COLOR)
+        } else if self.reused_nodes.contains(&node) {
+            Some(REUSED_COLOR)
+        } else {
+            None
+        }
+    }
+}
+
+/// `s` as a DOT double-quoted string. Newlines become `\n` line breaks.
+pub fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => (),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+type Attributes = Vec<(&'static str, String)>;
+
+/// Builds DOT source statement by statement, indenting nested blocks and quoting every
+/// attribute value.
+struct DotWriter {
+    s: String,
+    depth: usize,
+}
+
+impl DotWriter {
+    fn new() -> Self {
+        DotWriter { s: String::new(), depth: 0 }
+    }
+
+    fn line(&mut self, line: &str) {
+        for _ in 0..self.depth {
+            self.s.push_str("    ");
+        }
+        self.s.push_str(line);
+        self.s.push('\n');
+    }
+
+    fn open(&mut self, header: &str) {
+        self.line(&format!("{} {{", header));
+        self.depth += 1;
+    }
+
+    fn close(&mut self) {
+        self.depth -= 1;
+        self.line("}");
+    }
+
+    fn attributes(attrs: &[(&'static str, String)]) -> String {
+        let attrs: Vec<String> = attrs.iter().map(|&(k, ref v)| format!("{}={}", k, quote(v))).collect();
+        format!("[{}]", attrs.join(", "))
+    }
+
+    fn statement(&mut self, kind: &str, attrs: &[(&'static str, String)]) {
+        let line = format!("{} {}", kind, DotWriter::attributes(attrs));
+        self.line(&line);
+    }
+
+    fn node(&mut self, node: NodeIndex, attrs: &[(&'static str, String)]) {
+        let line = format!("n{} {}", node, DotWriter::attributes(attrs));
+        self.line(&line);
+    }
+
+    fn edge(&mut self, from: NodeIndex, to: NodeIndex) {
+        self.line(&format!("n{} -> n{}", from, to));
+    }
+
+    fn finish(self) -> String {
+        assert_eq!(self.depth, 0);
+        self.s
+    }
+}
+
+/// `n` bytes with a binary unit, e.g. `1.5 MB`.
+fn human_bytes(n: usize) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = n as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", n)
+    } else {
+        format!("{:.1} {}", value, units[unit])
+    }
+}
+
+pub fn shorten(s: &str, max: usize) -> String {
+    if s.chars().count() <= max {
+        String::from(s)
+    } else {
+        format!("{}…", s.chars().take(max - 1).collect::<String>())
+    }
+}
+
+fn label_lines(node: &TestNode, graph: &Graph) -> Vec<String> {
+    let mut lines = vec![format!("{} {}", node.symbol(), node.name)];
+    match node.data {
+        TestNodeData::Base { ref primary_key } if !primary_key.is_empty() => {
+            let keys: Vec<&str> = primary_key.iter().map(|c| c.name.as_str()).collect();
+            lines.push(format!("key: {}", keys.join(", ")));
+        }
+        TestNodeData::InnerJoin | TestNodeData::OuterJoin if !node.join_keys.is_empty() => {
+            let keys: Vec<String> = node.join_keys.iter().map(|k| k.to_string()).collect();
+            lines.push(format!("on {}", keys.join(", ")));
+        }
+        TestNodeData::Leaf if !node.reader_key.is_empty() => {
+            let keys: Vec<String> = node.reader_key.iter().map(|c| c.qualified_name()).collect();
+            lines.push(format!("key: {}", keys.join(", ")));
+        }
+        TestNodeData::Project => {
+            let mut columns: Vec<&str> = node.columns.iter().take(MAX_LABEL_COLUMNS).map(|c| c.output_name()).collect();
+            let rest = format!("+{} more", node.columns.len().saturating_sub(MAX_LABEL_COLUMNS));
+            if node.columns.len() > MAX_LABEL_COLUMNS {
+                columns.push(&rest);
+            }
+            lines.push(columns.join(", "));
+        }
+        _ => (),
+    }
+    lines.push(format!("{} rows, {}", node.maxrows, human_bytes(node.estimated_bytes())));
+    for query in node.leaf_of(graph) {
+        lines.push(format!("query: {}", shorten(query, MAX_QUERY_NAME)));
+    }
+    lines
+}
+
+/// Node attributes: a shape per kind, the multi-line label, a fill that darkens with the
+/// number of queries sharing the node, and a thick border if `options` highlights it.
+pub fn describe(node: &TestNode, graph: &Graph, options: &DotOptions) -> Vec<(&'static str, String)> {
+    let (shape, style) = match node.data {
+        TestNodeData::Base{..} => ("cylinder", "bold"),
+        TestNodeData::InnerJoin => ("box", "rounded,bold"),
+        TestNodeData::OuterJoin => ("box", "rounded,bold,dashed"),
+        TestNodeData::Project => ("ellipse", "bold"),
+        TestNodeData::Leaf => ("doubleoctagon", "bold"),
+        TestNodeData::UnimplementedNode => ("octagon", "dashed"),
+    };
+    let mut attrs: Attributes = vec![("shape", String::from(shape)),
+                                     ("label", label_lines(node, graph).join("\n"))];
+
+    let users = node.users.len();
+    if users > 1 && !node.is_base() {
+        let shade = users.min(7);
+        attrs.push(("style", format!("{},filled", style)));
+        attrs.push(("colorscheme", String::from("blues9")));
+        attrs.push(("fillcolor", shade.to_string()));
+        if shade >= 5 {
+            attrs.push(("fontcolor", String::from("white")));
+        }
+        attrs.push(("tooltip", format!("shared by {} queries", users)));
+    } else {
+        attrs.push(("style", String::from(style)));
+    }
+    if let Some(color) = options.highlight(node.index) {
+        attrs.push(("color", String::from(color)));
+        attrs.push(("penwidth", String::from("3")));
+    }
+    attrs
+}
+
+/// The cluster each non-base node is drawn in, as the name of the query owning the cluster.
+/// Queries are ordered by the index of their output node, so a node shared by several
+/// queries lands with the earliest of them.
+fn clusters(graph: &Graph) -> BTreeMap<NodeIndex, (String, Vec<NodeIndex>)> {
+    let mut leaves: BTreeMap<&str, NodeIndex> = BTreeMap::new();
+    for node in graph {
+        for query in node.leaf_of(graph) {
+            leaves.entry(query).or_insert(node.index);
+        }
+    }
+    let mut clusters: BTreeMap<NodeIndex, (String, Vec<NodeIndex>)> = BTreeMap::new();
+    for node in graph.iter().filter(|n| !n.is_base()) {
+        let owner = node.users.iter()
+                              .filter_map(|u| leaves.get(u.as_str()).map(|&leaf| (leaf, u)))
+                              .min();
+        if let Some((leaf, query)) = owner {
+            clusters.entry(leaf).or_insert_with(|| (query.clone(), Vec::new())).1.push(node.index);
+        }
+    }
+    clusters
+}
+
+pub fn graphviz(graph: &Graph) -> String {
+    graphviz_with(graph, &DotOptions::default())
+}
+
+/// Writes `graph` as DOT. Nodes appear in index order and edges sorted by their endpoints,
+/// so the same graph always produces the same text.
+pub fn graphviz_with(graph: &Graph, options: &DotOptions) -> String {
+    let mut dot = DotWriter::new();
 
     // header.
-    s.push_str("digraph {{\n");
+    dot.open("digraph");
 
     // global formatting.
-    indentln(&mut s);
-    s.push_str("graph [ fontsize=24 fontcolor=\"#0C6fA9\", outputorder=edgesfirst ]\n");
-    s.push_str("edge [ color=\"#0C6fA9\", style=bold ]\n");
-    s.push_str("node [ color=\"#0C6fA9\", shape=box, style=\"rounded,bold\" ]\n");
+    dot.statement("graph", &[("fontsize", String::from("24")),
+                             ("fontcolor", String::from(COLOR)),
+                             ("outputorder", String::from("edgesfirst"))]);
+    dot.statement("edge", &[("color", String::from(COLOR)), ("style", String::from("bold"))]);
+    dot.statement("node", &[("color", String::from(COLOR)),
+                            ("shape", String::from("box")),
+                            ("style", String::from("rounded,bold"))]);
 
     // node descriptions.
-    for node in graph {
-        indentln(&mut s);
-        s.push_str(&format!("n{}", node.borrow().index));
-        s.push_str(&node.borrow().describe());
+    let clusters = if options.clustered { clusters(graph) } else { BTreeMap::new() };
+    let clustered: Vec<NodeIndex> = clusters.values().flat_map(|(_, nodes)| nodes.iter().cloned()).collect();
+    for node in graph.iter().filter(|n| !clustered.contains(&n.index)) {
+        dot.node(node.index, &describe(node, graph, options));
+    }
+    for (i, (query, nodes)) in clusters.values().enumerate() {
+        dot.open(&format!("subgraph cluster_{}", i));
+        dot.statement("graph", &[("label", shorten(query, MAX_QUERY_NAME)), ("style", String::from("dashed"))]);
+        for &node in nodes.iter() {
+            dot.node(node, &describe(&graph[node], graph, options));
+        }
+        dot.close();
     }
 
     // edges.
-    for node in graph {
-        for child in node.borrow().children.clone() {
-            indentln(&mut s);
-            s.push_str(&format!(
-                "n{} -> n{} [ ]",
-                node.borrow().index,
-                child.borrow().index
-            ));
-            s.push_str("\n");
-        }
+    let mut edges: Vec<(NodeIndex, NodeIndex)> = graph.iter()
+        .flat_map(|node| node.children.iter().map(move |&child| (node.index, child)))
+        .collect();
+    edges.sort();
+    for (from, to) in edges {
+        dot.edge(from, to);
     }
 
     // footer.
-    s.push_str("}}");
+    dot.close();
+
+    dot.finish()
+}
+
+
+#[test]
+fn test_quote() {
+    assert_eq!(quote("plain"), "\"plain\"");
+    assert_eq!(quote("say \"hi\"\\now\nthen"), "\"say \\\"hi\\\"\\\\now\\nthen\"");
+    assert_eq!(quote("`comments`"), "\"`comments`\"");
+}
+
+#[test]
+fn test_graphviz_labels() {
+    use graph::plan_queries;
+    use std::path::Path;
+    use {load_queries, Optimizations};
 
-    s
+    let mut queries = load_queries(Path::new("tests/tpcw-views.txt")).unwrap();
+    queries.push(String::from("CREATE VIEW c AS SELECT c_id FROM customer JOIN orders ON (customer.c_id = orders.o_c_id);"));
+    let report = plan_queries(&queries, &Optimizations::new());
+    let dot = graphviz(&report.graph);
+
+    assert!(dot.starts_with("digraph {\n"));
+    assert!(dot.ends_with("\n}\n"));
+    assert!(dot.contains("[shape=\"cylinder\", label=\"B customer\\nkey: c_id\\n2880000 rows, "));
+    // all three views share the join, which carries its key
+    let join = &report.graph[report.graph[report.tables["a"]].ancestors[0]].ancestors[0];
+    let line = dot.lines().find(|l| l.trim_start().starts_with(&format!("n{} [", join))).unwrap();
+    assert!(line.contains("label=\"⋈ join\\non customer.c_id = orders.o_c_id\\n"));
+    assert!(line.contains("fillcolor=\"3\""));
+    assert!(line.contains("tooltip=\"shared by 3 queries\""));
+    assert!(dot.contains("[shape=\"doubleoctagon\", label=\"≡ c\\n2592000 rows, 0 B\\nquery: c\""));
+    assert!(dot.contains("[shape=\"ellipse\", label=\"π project\\nc_id\\n"));
+
+    // clustered: the shared join is drawn with a, the view planned first
+    let clustered = graphviz_with(&report.graph, &DotOptions { clustered: true, ..DotOptions::default() });
+    assert_eq!(clustered.matches("subgraph cluster_").count(), 3);
+    let cluster_a = clustered.find("label=\"a\"").unwrap();
+    let cluster_b = clustered.find("label=\"b\"").unwrap();
+    let join_at = clustered.find(&format!("n{} [", join)).unwrap();
+    assert!(cluster_a < join_at && join_at < cluster_b);
+
+    // anonymous queries are named by their position and end in a leaf of that name
+    let mut queries = load_queries(Path::new("tests/tpcw-views.txt")).unwrap();
+    queries.push(String::from("SELECT i_id FROM item WHERE i_id = \"a\""));
+    let dot = graphviz(&plan_queries(&queries, &Optimizations::new()).graph);
+    assert!(dot.contains("[shape=\"doubleoctagon\", label=\"≡ query_11\\n10000 rows, 0 B\\nquery: query_11\""));
+}
+
+/// Compares the DOT output for the checked-in workloads with the snapshots in `tests/golden`.
+/// Run with `UPDATE_GOLDEN=1` to rewrite the snapshots after an intended change.
+#[test]
+fn test_graphviz_golden() {
+    use graph::plan_queries;
+    use pipeline::Pass;
+    use std::env;
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::path::Path;
+    use {load_queries, Optimizations};
+
+    let flags = |overlap, permutations, sorted_names, nonprefix, megajoin| {
+        Optimizations { overlap, permutations, sorted_names, nonprefix, megajoin, ..Optimizations::new() }
+    };
+    let cases = vec![
+        ("tpc-w-none", "tpc-w-queries", flags(false, false, false, false, false), false),
+        ("tpc-w-overlap", "tpc-w-queries", flags(true, false, false, false, false), false),
+        ("tpc-w-permutations", "tpc-w-queries", flags(true, true, false, false, false), false),
+        ("tpc-w-nonprefix", "tpc-w-queries", flags(true, false, false, true, false), false),
+        ("tpc-w-sorted", "tpc-w-queries", flags(true, false, true, false, false), false),
+        ("tpc-w-megajoin", "tpc-w-queries", flags(false, false, false, false, true), false),
+        ("tpc-w-subsets-order", "tpc-w-queries",
+         Optimizations::with_passes(vec![Pass::MatchSubsets, Pass::ChooseJoinOrder, Pass::MatchReuse]), false),
+        ("tpc-w-clustered", "tpc-w-queries", flags(true, true, false, false, false), true),
+        ("combo-join-overlap", "combo-join", flags(true, false, false, false, false), false),
+        ("long-join-overlap", "long-join", flags(true, false, false, false, false), false),
+        ("lobsters-overlap", "lobsters-schema", flags(true, false, false, false, false), false),
+    ];
+
+    let update = env::var("UPDATE_GOLDEN").is_ok();
+    for (name, workload, opts, clustered) in cases {
+        let report = plan_queries(&load_queries(Path::new(&format!("tests/{}.txt", workload))).unwrap(), &opts);
+        let dot = graphviz_with(&report.graph, &DotOptions { clustered, ..DotOptions::default() });
+        assert_eq!(dot, graphviz_with(&report.graph, &DotOptions { clustered, ..DotOptions::default() }));
+
+        let path = format!("tests/golden/{}.dot", name);
+        if update {
+            File::create(&path).unwrap().write_all(dot.as_bytes()).unwrap();
+            continue;
+        }
+        let mut expected = String::new();
+        File::open(&path).unwrap_or_else(|e| panic!("{}: {}; run with UPDATE_GOLDEN=1", path, e))
+                         .read_to_string(&mut expected).unwrap();
+        assert!(dot == expected, "{} differs from {}; run with UPDATE_GOLDEN=1 if the change is intended", name, path);
+    }
 }
@@ -0,0 +1,126 @@
+extern crate nom_sql;
+
+use graph::{BaseColumn, Graph, NodeIndex, TestNodeData};
+use resolve::ResolvedColumn;
+use strategy::JoinPredicate;
+
+use std::collections::HashMap;
+
+
+/// A view read by a query, taken apart so that the query can join the view's tables itself
+/// instead of reading the view's leaf.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InlinedView {
+    /// The view.
+    pub view: String,
+    /// Name the query refers to the view by.
+    pub relation: String,
+    /// The base tables the view joins, in the order the view's plan reads them.
+    pub tables: Vec<String>,
+    /// The view's join keys, qualified with table names.
+    pub predicates: Vec<JoinPredicate>,
+    /// The base column behind each output column of the view.
+    pub columns: HashMap<String, BaseColumn>,
+}
+
+impl InlinedView {
+    fn base(&self, column: &nom_sql::Column) -> Option<&BaseColumn> {
+        match column.table {
+            Some(ref table) if *table == self.relation => self.columns.get(&column.name),
+            _ => None,
+        }
+    }
+}
+
+fn qualified(base: &BaseColumn) -> nom_sql::Column {
+    nom_sql::Column { name: base.name.clone(), alias: None, table: Some(base.table.clone()), function: None }
+}
+
+/// The view `tables[view]` taken apart, for a query that refers to it as `relation`. Only
+/// views that inner-join base tables and output their columns unchanged can be inlined: the
+/// outer query cannot see through aggregates, computed columns or outer joins, and a view
+/// that joins a table under an alias is left alone since the query could not tell the
+/// instances apart.
+pub fn inline_view(view: &str, relation: &str, tables: &HashMap<String, NodeIndex>, graph: &Graph) -> Option<InlinedView> {
+    let leaf = &graph[*tables.get(view)?];
+    if leaf.data != TestNodeData::Leaf {
+        return None;
+    }
+    let project = &graph[*leaf.ancestors.first()?];
+    let input = *project.ancestors.first()?;
+
+    let mut nodes = graph.ancestor_closure(input);
+    nodes.sort();
+    let mut base_tables = Vec::new();
+    let mut predicates = Vec::new();
+    for &n in nodes.iter() {
+        let node = &graph[n];
+        match node.data {
+            TestNodeData::Base { .. } => base_tables.push(node.name.clone()),
+            TestNodeData::InnerJoin => predicates.extend(node.join_keys.iter().cloned()),
+            TestNodeData::Project if node.ancestors.iter().all(|&a| graph[a].is_base()) => (),
+            _ => return None,
+        }
+    }
+    if base_tables.iter().any(|t| tables.get(t).is_none_or(|&n| !graph[n].is_base())) {
+        return None;
+    }
+    let in_view = |c: &nom_sql::Column| c.table.as_ref().is_some_and(|t| base_tables.contains(t));
+    if !predicates.iter().all(|p| in_view(&p.left) && in_view(&p.right)) {
+        return None;
+    }
+
+    let mut columns = HashMap::new();
+    for column in project.columns.iter() {
+        match (&column.table, column.lineage.as_slice()) {
+            (Some(table), [base]) if column.inputs.is_empty() && base.table == *table && base.name == column.name => {
+                columns.insert(String::from(column.output_name()), base.clone());
+            }
+            _ => return None,
+        }
+    }
+    Some(InlinedView { view: String::from(view), relation: String::from(relation), tables: base_tables, predicates, columns })
+}
+
+/// `relations` with every inlined view replaced by its tables.
+pub fn relations(relations: &[String], views: &[InlinedView]) -> Vec<String> {
+    let mut inlined = Vec::new();
+    for r in relations.iter() {
+        match views.iter().find(|v| v.view == *r) {
+            Some(view) => inlined.extend(view.tables.iter().cloned()),
+            None => inlined.push(r.clone()),
+        }
+    }
+    inlined
+}
+
+/// `predicates` with columns of inlined views replaced by the base columns behind them, plus
+/// the views' own join keys.
+pub fn predicates(predicates: &[JoinPredicate], views: &[InlinedView]) -> Vec<JoinPredicate> {
+    let rewrite = |c: &nom_sql::Column| views.iter().find_map(|v| v.base(c)).map_or_else(|| c.clone(), qualified);
+    predicates.iter()
+              .map(|p| JoinPredicate { left: rewrite(&p.left), right: rewrite(&p.right) })
+              .chain(views.iter().flat_map(|v| v.predicates.iter().cloned()))
+              .collect()
+}
+
+/// `needed` with columns of inlined views replaced by the base columns behind them, plus the
+/// columns the views join on.
+pub fn needed(needed: &[ResolvedColumn], views: &[InlinedView]) -> Vec<ResolvedColumn> {
+    let base = |b: &BaseColumn| ResolvedColumn { relation: b.table.clone(), table: b.table.clone(), column: b.name.clone() };
+    let mut columns: Vec<ResolvedColumn> = needed.iter()
+        .map(|n| match views.iter().find(|v| v.relation == n.relation) {
+            Some(view) => view.columns.get(&n.column).map_or_else(|| n.clone(), base),
+            None => n.clone(),
+        })
+        .collect();
+    for p in views.iter().flat_map(|v| v.predicates.iter()) {
+        for c in [&p.left, &p.right].iter() {
+            let table = c.table.clone().unwrap_or_default();
+            columns.push(ResolvedColumn { relation: table.clone(), table, column: c.name.clone() });
+        }
+    }
+    columns.sort();
+    columns.dedup();
+    columns
+}
@@ -1,9 +1,5 @@
-extern crate permutohedron;
+use graph::{Column, Graph, NodeIndex, TestNodeData};
 
-use graph::{TestNodeRef, TestNode, TestNodeData, get_empty_node};
-use Optimizations;
-
-use self::permutohedron::Heap;
 use std::collections::HashMap;
 use std::cmp;
 
@@ -11,91 +7,54 @@ use std::cmp;
 
 
 
-pub fn make_inner_join(n1: &TestNodeRef, n2: &TestNodeRef, graph: &mut Vec<TestNodeRef>) -> TestNodeRef {
-    let maxrows = cmp::min(n1.borrow().maxrows, n2.borrow().maxrows);  // assuming primary key for now
-    let mut columns = n1.borrow().columns.clone();
-    columns.append(&mut n2.borrow().columns.clone());
-    let node = TestNode::new(
+pub fn make_inner_join(n1: NodeIndex, n2: NodeIndex, graph: &mut Graph) -> NodeIndex {
+    let maxrows = cmp::min(graph[n1].maxrows, graph[n2].maxrows);  // assuming primary key for now
+    let mut columns = graph.output_columns(n1);
+    columns.append(&mut graph.output_columns(n2));
+    graph.add_node(
         "join",
-        graph.len(),
         TestNodeData::InnerJoin,
         columns,
-        vec![n1.clone(), n2.clone()], // ancestors
-        Vec::new(), // children,
+        vec![n1, n2], // ancestors
         maxrows,
-    );
-    graph.push(node.clone());
-    node
+    )
 }
 
-pub fn make_outer_join(n1: &TestNodeRef, n2: &TestNodeRef, graph: &mut Vec<TestNodeRef>) -> TestNodeRef {
-    let maxrows = n1.borrow().maxrows + n2.borrow().maxrows; // assuming primary key for now
-    let mut columns = n1.borrow().columns.clone();
-    columns.append(&mut n2.borrow().columns.clone());
-    let node = TestNode::new(
+pub fn make_outer_join(n1: NodeIndex, n2: NodeIndex, graph: &mut Graph) -> NodeIndex {
+    let maxrows = graph[n1].maxrows + graph[n2].maxrows; // assuming primary key for now
+    let mut columns = graph.output_columns(n1);
+    // rows of n1 without a match have NULLs for n2's columns
+    columns.extend(graph.output_columns(n2).into_iter().map(|c| Column { nullable: true, ..c }));
+    graph.add_node(
         "outer join",
-        graph.len(),
         TestNodeData::OuterJoin,
         columns,
-        vec![n1.clone(), n2.clone()], // ancestors
-        Vec::new(), // children
+        vec![n1, n2], // ancestors
         maxrows,
-    );
-    graph.push(node.clone());
-    node
+    )
 }
 
-pub fn overlap_existing(n1: &TestNodeRef, n2: &TestNodeRef, graph: &mut Vec<TestNodeRef>, opts: Optimizations) -> Option<TestNodeRef> {
-    if !opts.overlap {
-        return None;
-    }
+pub fn find_join(n1: NodeIndex, n2: NodeIndex, graph: &Graph) -> Option<NodeIndex> {
     // check whether a join already exists of these nodes
     for node in graph {
-        if node.borrow().data == TestNodeData::InnerJoin {
-            let ancestors = node.borrow().ancestors.clone();
-            if ancestors.len() != 2 {
-                unimplemented!();
-            }
-            if ancestors.contains(n1) && ancestors.contains(n2) {
-                return Some(node.clone());
-            }
+        if node.data == TestNodeData::InnerJoin && node.ancestors.len() == 2
+                && node.ancestors.contains(&n1) && node.ancestors.contains(&n2) {
+            return Some(node.index);
         }
     }
     None
 }
 
-pub fn make_all_joins(joinable_names: Vec<String>, tables: &HashMap<String, TestNodeRef>,
-                      graph: &mut Vec<TestNodeRef>, opts: Optimizations) -> TestNodeRef {
-    // join all entries of tables and joins together; TODO make this use on/where
-    if joinable_names.len() == 0 {
-        unimplemented!();
-    }
-
-    let mut name = joinable_names[0].clone();
-    let mut prev_node = tables.get(&name).unwrap().clone();
-    let mut next_node = prev_node.clone();
-    for i in 1..joinable_names.len() {
-        name = joinable_names[i].clone();
-        next_node = tables.get(&name).unwrap().clone();
-        match overlap_existing(&prev_node, &next_node, graph, opts.clone()) {
-            Some (overlap_node) => prev_node = overlap_node,
-            None => prev_node = make_inner_join(&prev_node, &next_node, graph),
-        }
-    }
-
-    prev_node
-}
-
-pub fn get_all_ancestors(node: &TestNodeRef) -> Vec<String> {
-    match node.borrow().data {
-        TestNodeData::Base{..} => Vec::new(),
+pub fn get_all_ancestors(node: NodeIndex, graph: &Graph) -> Vec<String> {
+    match graph[node].data {
+        // a query reading a view joins its leaf like a table, not the tables behind it
+        TestNodeData::Base{..} | TestNodeData::Leaf => Vec::new(),
         _ => {
-            let mut ancs: Vec<String> = node.borrow().ancestors.clone()
-                                                     .into_iter()
-                                                     .map(|anc| anc.borrow().name.clone())
-                                                     .collect();
-            for anc in node.borrow().ancestors.clone() {
-                ancs.append(&mut get_all_ancestors(&anc));
+            let mut ancs: Vec<String> = graph[node].ancestors.iter()
+                                                   .map(|&anc| graph[anc].name.clone())
+                                                   .collect();
+            for &anc in graph[node].ancestors.iter() {
+                ancs.append(&mut get_all_ancestors(anc, graph));
             }
 
             ancs
@@ -103,127 +62,48 @@ pub fn get_all_ancestors(node: &TestNodeRef) -> Vec<String> {
     }
 }
 
-pub fn all_acceptable(tables: &Vec<String>, joinable_names: &Vec<String>) -> bool {
+pub fn all_acceptable(tables: &[String], joinable_names: &[String]) -> bool {
     for t in tables {
-        if !joinable_names.contains(&t) {
+        if !joinable_names.contains(t) {
             return false;
         }
     }
     true
 }
 
-pub fn some_needed(tables: &Vec<String>, joinable_names: &Vec<String>, covered_tables: &Vec<String>) -> bool {
+pub fn some_needed(tables: &[String], joinable_names: &[String], covered_tables: &[String]) -> bool {
     let mut count = 0;
     for t in tables {
-        if joinable_names.contains(&t) && !covered_tables.contains(&t) {
+        if joinable_names.contains(t) && !covered_tables.contains(t) {
             count += 1;
         }
     }
     count >= 2
 }
 
-pub fn make_joins_nonprefix_overlap(joinable_names: Vec<String>, tables: &HashMap<String, TestNodeRef>,
-                                    graph: &mut Vec<TestNodeRef>, opts: Optimizations) -> TestNodeRef {
-    let mut covered_tables: Vec<String> = Vec::new();
-    let mut existing_joins: Vec<TestNodeRef> = Vec::new();
-    for node in graph.clone() {
-        if node.borrow().data == TestNodeData::InnerJoin {
-            let mut tables = get_all_ancestors(&node);
-            if all_acceptable(&tables, &joinable_names) && some_needed(&tables, &joinable_names, &covered_tables) {
-                existing_joins.push(node);
-                covered_tables.append(&mut tables);
-            }
-        }
-    }
-    if existing_joins.len() == 0 {
-        return make_all_joins(joinable_names, tables, graph, opts);
-    }
-    let mut prev_node = existing_joins[0].clone();
-    let mut next_node = prev_node.clone();
-    for i in 1..existing_joins.len() {
-        prev_node = make_inner_join(&prev_node, &existing_joins[i], graph);
-    }
-    for name in joinable_names {
-        if covered_tables.contains(&name) {
-            continue;
-        }
-        next_node = tables.get(&name).unwrap().clone();
-        match overlap_existing(&prev_node, &next_node, graph, opts.clone()) {
-            Some (overlap_node) => prev_node = overlap_node,
-            None => prev_node = make_inner_join(&prev_node, &next_node, graph),
-        }
-    }
-
-    prev_node
-}
-
-pub fn make_joins_with_permutations(joinable_names: Vec<String>, tables: &HashMap<String, TestNodeRef>,
-                                    graph: &mut Vec<TestNodeRef>, opts: Optimizations) -> TestNodeRef {
-    let mut names = joinable_names.clone();
-    let heap = Heap::new(&mut names);
-
-    let mut best_order = joinable_names.clone();
-    let mut best_overlap = 0;
-
-    for name_order in heap {
-        let mut overlap = 0;
-
-        let mut name = name_order[0].clone();
-        let mut prev_node = tables.get(&name).unwrap().clone();
-        let mut next_node = prev_node.clone();
-        for i in 1..name_order.len() {
-            name = name_order[i].clone();
-            next_node = tables.get(&name).unwrap().clone();
-            match overlap_existing(&prev_node, &next_node, graph, opts.clone()) {
-                Some (overlap_node) => prev_node = overlap_node,
-                None => break,
-            }
-            overlap += 1;
-        }
-
-        if overlap > best_overlap {
-            best_overlap = overlap;
-            best_order = name_order.clone();
-        }
-    }
-
-    make_all_joins(best_order, tables, graph, opts)
-}
-
-pub fn make_combined_joins(joinable_names: Vec<String>, tables: &HashMap<String, TestNodeRef>,
-                           graph: &mut Vec<TestNodeRef>, opts: Optimizations) -> TestNodeRef {
+pub fn make_combined_joins(joinable_names: Vec<String>, tables: &HashMap<String, NodeIndex>,
+                           graph: &mut Graph) -> NodeIndex {
     // join all entries of tables and joins together; TODO make this use on/where
-    let empty_node = get_empty_node();
-    let mut previous_base: Option<&TestNodeRef> = Some(&empty_node);
-    let mut previous_join: Option<TestNodeRef> = None;
+    let mut previous_base: Option<NodeIndex> = None;
+    let mut previous_join: Option<NodeIndex> = None;
 
     let mut already_joined_names: Vec<String> = Vec::new();
-    for node in graph.clone() {
-        if node.borrow().data == TestNodeData::OuterJoin {
-            let ancestors = node.borrow().ancestors.clone();
-            if ancestors.len() != 2 {
-                unimplemented!();
-            }
-            match ancestors[0].borrow().data{
-                TestNodeData::Base {..} => {
-                    already_joined_names.push(ancestors[0].borrow().name.clone());
-                },
-                _ => (),
-            }
-            match ancestors[1].borrow().data{
-                TestNodeData::Base {..} => {
-                    already_joined_names.push(ancestors[1].borrow().name.clone());
-                },
-                _ => (),
+    for node in graph.iter() {
+        if node.data == TestNodeData::OuterJoin && node.ancestors.len() == 2 {
+            for &ancestor in node.ancestors.iter() {
+                // a view, or a base table read directly or through its narrow projection
+                let source = match graph[ancestor].data {
+                    TestNodeData::Project => graph[ancestor].ancestors.first().cloned(),
+                    _ => Some(ancestor),
+                };
+                if let Some(source) = source.filter(|&s| graph[s].is_base() || graph[s].data == TestNodeData::Leaf) {
+                    already_joined_names.push(graph[source].name.clone());
+                }
             }
-            previous_join = Some(node.clone());
+            previous_join = Some(node.index);
         }
     }
 
-    if previous_join == None {
-        previous_base = None;
-    }
-
     let mut names: Vec<String> = Vec::new();
     for name in joinable_names {
         if !already_joined_names.contains(&name) && !names.contains(&name) {
@@ -232,26 +112,21 @@ pub fn make_combined_joins(joinable_names: Vec<String>, tables: &HashMap<String,
     }
 
     for name in names {
-        match previous_base {
-            None => previous_base = tables.get(&name),
-            Some (base) => {
-                // prev is either referencing a base table, or a join thereof
-                let base_to_add = tables.get(&name).unwrap();
-                // check whether we already have a join node in the graph between these nodes
-                match previous_join {
-                    None => previous_join = Some(make_outer_join(base, base_to_add, graph)),
-                    Some (prev) => previous_join = Some(make_outer_join(&prev, base_to_add, graph)),
-                }
-                previous_base = Some(base_to_add);
-            },
+        // prev is either referencing a base table, or a join thereof
+        let base_to_add = *tables.get(&name).unwrap();
+        match (previous_join, previous_base) {
+            (None, None) => (),
+            (None, Some(base)) => previous_join = Some(make_outer_join(base, base_to_add, graph)),
+            (Some(prev), _) => previous_join = Some(make_outer_join(prev, base_to_add, graph)),
         }
+        previous_base = Some(base_to_add);
     }
-    let join_result = match previous_join {
+
+    match previous_join {
         Some(j) => j,
         None => match previous_base {
-            Some(j) => j.clone(),
-            None => unimplemented!(),
+            Some(j) => j,
+            None => unreachable!("make_select rejects queries without relations"),
         },
-    };
-    join_result
+    }
 }
@@ -1,87 +1,243 @@
 extern crate nom_sql;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
 
 
 use self::graph::parse_queries;
 
-use std::fs::File;
-use std::io::Read;
 use std::path::Path;
 
+mod compare;
+mod config;
+mod export;
 mod graph;
 mod graphviz;
+mod inline;
 mod join;
+mod pipeline;
+mod prune;
+mod render;
+mod report;
+mod resolve;
+mod saved;
+mod snapshot;
+mod stats;
+mod strategy;
+mod svg;
+mod workload;
 
+pub use compare::{ReportFormat, StrategyResult, compare_strategies, format_comparison};
+pub use config::{Config, CostModel, OutputConfig, PlannerConfig, ReportConfig, Statistics};
+pub use export::{DotExporter, ExportedLink, ExportedNode, Exporter, GraphMlExporter, JsonGraphExporter, MermaidExporter};
+pub use export::{NodeLinkGraph, exporter_names, get_exporter};
+pub use graph::{BYTES_PER_COLUMN, BaseColumn, Column, Graph, NodeIndex, TestNode, TestNodeData, extend_queries, extend_queries_observed, plan_queries};
+pub use graphviz::{DotOptions, graphviz, graphviz_with, quote};
+pub use pipeline::{Pass, Pipeline};
+pub use render::{NATIVE_RENDERER, render, render_with_fallback};
+pub use report::{Location, ParseFailure, PlanError, PlanReport, QueryResult};
+pub use resolve::{RelationInstance, Resolution, ResolvedColumn, resolve_select};
+pub use saved::{SavedGraph, SavedNode, SavedQuery};
+pub use snapshot::{Step, StepWriter};
+pub use stats::{WorkloadStats, workload_stats};
+pub use strategy::{JoinPredicate, JoinStrategy, MegaJoin, NonprefixJoins, PermutationJoins, PipelineJoins, PrefixJoins};
+pub use strategy::{get_strategy, join_predicates, register_strategy, strategy_names};
+pub use svg::{Layout, Vertex, svg, svg_with};
+pub use workload::{Statement, load_statements, split_statements};
 
-#[derive(Clone)]
+
+/// Planner configuration. The five flags are shorthand for common pipelines (see
+/// `Pipeline::from_flags`); setting `passes` replaces them with an explicit pass list.
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct Optimizations {
     pub overlap: bool,
     pub permutations: bool,
     pub sorted_names: bool,
     pub nonprefix: bool,
-    pub megajoin: bool
+    pub megajoin: bool,
+    /// Passes to run instead of the ones the flags above stand for.
+    pub passes: Option<Pipeline>,
+    /// Name of a registered `JoinStrategy` to use instead of the pass pipeline.
+    pub join_strategy: Option<String>,
+    pub statistics: Statistics,
+    pub cost: CostModel,
 }
 
 impl Optimizations {  // default constructor
-    fn new() -> Self {
+    pub fn new() -> Self {
         Optimizations{
             overlap: true,
             permutations: true,
             sorted_names: false,
             nonprefix: false,
-            megajoin: false}
+            megajoin: false,
+            passes: None,
+            join_strategy: None,
+            statistics: Statistics::default(),
+            cost: CostModel::default()}
     }
-}
 
+    /// Plans with exactly these passes, ignoring the flags.
+    pub fn with_passes(passes: Vec<Pass>) -> Self {
+        Optimizations{
+            overlap: false,
+            permutations: false,
+            sorted_names: false,
+            nonprefix: false,
+            megajoin: false,
+            passes: Some(Pipeline::new(passes)),
+            join_strategy: None,
+            statistics: Statistics::default(),
+            cost: CostModel::default()}
+    }
 
-pub fn test_queries_from_file(f: &Path, name: &str, opts: Optimizations, outf: Option<&Path>) -> Result<i32, i32> {
-    let mut f = File::open(f).unwrap();
-    let mut s = String::new();
+    /// The passes the default `pipeline` strategy runs.
+    pub fn pipeline(&self) -> Pipeline {
+        match self.passes {
+            Some(ref pipeline) => pipeline.clone(),
+            None => Pipeline::from_flags(self),
+        }
+    }
 
-    // Load queries
-    f.read_to_string(&mut s).unwrap();
-    let lines: Vec<String> = s
-        .lines()
-        .filter(|l| {
-            !l.is_empty() && !l.starts_with("#") && !l.starts_with("--") && !l.starts_with("/*")
-        })
-        .map(|l| {
-            if l.starts_with("VIEW") {
-                let v: Vec<&str> = l.splitn(2, ":").collect();
-                format!("CREATE VIEW {} AS{}", &v[0][5..], v[1])
-            }
-            else if l.starts_with("QUERY") {
-                let v: Vec<&str> = l.splitn(2, ":").collect();
-                format!("CREATE VIEW {} AS{}", &v[0][6..], v[1])
-            }
-            else {
-                String::from(l)
-            }
-        })
-        .map(|l| {
-            if !(l.ends_with("\n") || l.ends_with(";")) {
-                l + "\n"
-            } else {
-                l
+    /// The registered join strategy `make_select` uses: `join_strategy` if set, otherwise
+    /// `pipeline`.
+    pub fn strategy_name(&self) -> String {
+        match self.join_strategy {
+            Some(ref name) => name.clone(),
+            None => String::from("pipeline"),
+        }
+    }
+
+    /// Every flag combination that plans differently, plus the pipelines no flag combination
+    /// can express. The flags honor only one join mode (megajoin, then permutations, then
+    /// nonprefix), megajoin never looks for overlap, and permutation search without overlap
+    /// always keeps the original order, so those combinations are left out.
+    pub fn all_combinations() -> Vec<Optimizations> {
+        let mut combos = Vec::new();
+        for &sorted_names in [false, true].iter() {
+            for &overlap in [false, true].iter() {
+                combos.push(Optimizations{overlap, permutations: false, sorted_names, nonprefix: false, megajoin: false, ..Optimizations::new()});
+                combos.push(Optimizations{overlap, permutations: false, sorted_names, nonprefix: true, megajoin: false, ..Optimizations::new()});
+                if overlap {
+                    combos.push(Optimizations{overlap, permutations: true, sorted_names, nonprefix: false, megajoin: false, ..Optimizations::new()});
+                }
             }
-        }).collect();
-    println!("Loaded {} {} queries", lines.len(), name);
+            combos.push(Optimizations{overlap: false, permutations: false, sorted_names, nonprefix: false, megajoin: true, ..Optimizations::new()});
+        }
+        // nonprefix matching followed by a search over the order of what is left
+        combos.push(Optimizations::with_passes(vec![Pass::MatchSubsets, Pass::ChooseJoinOrder, Pass::MatchReuse]));
+        combos.push(Optimizations::with_passes(vec![Pass::CanonicalizeNames, Pass::MatchSubsets, Pass::ChooseJoinOrder, Pass::MatchReuse]));
+        combos
+    }
+
+    /// Short name for this combination, e.g. `overlap+permutations`, or `none` if nothing is set.
+    /// An explicit pass list is named by `Pipeline::label` instead, and an explicit join strategy
+    /// is appended as `@name`.
+    pub fn label(&self) -> String {
+        let flags: Vec<&str> = [(self.overlap, "overlap"),
+                                (self.permutations, "permutations"),
+                                (self.sorted_names, "sorted"),
+                                (self.nonprefix, "nonprefix"),
+                                (self.megajoin, "megajoin")]
+            .iter()
+            .filter(|&&(on, _)| on)
+            .map(|&(_, name)| name)
+            .collect();
+        let flags = if let Some(ref pipeline) = self.passes {
+            pipeline.label()
+        } else if flags.is_empty() {
+            String::from("none")
+        } else {
+            flags.join("+")
+        };
+        match self.join_strategy {
+            Some(ref name) => format!("{}@{}", flags, name),
+            None => flags,
+        }
+    }
+}
+
+impl Default for Optimizations {
+    fn default() -> Self {
+        Optimizations::new()
+    }
+}
+
+
+/// Reads a workload file into its statements' SQL, see `split_statements`.
+pub fn load_queries(f: &Path) -> Result<Vec<String>, PlanError> {
+    Ok(read_statements(f)?.into_iter().map(|s| s.sql).collect())
+}
+
+fn read_statements(f: &Path) -> Result<Vec<Statement>, PlanError> {
+    load_statements(f).map_err(|e| PlanError::Io { path: f.display().to_string(), error: e.to_string() })
+}
+
+/// Plans the workload in `f` and prints its report. Returns `Err` with the same report if any
+/// statement failed to parse or was skipped or the graph cannot be written to `outf`, or with an
+/// empty report if `f` cannot be read.
+pub fn test_queries_from_file(f: &Path, name: &str, opts: Optimizations, outf: Option<&Path>) -> Result<PlanReport, Box<PlanReport>> {
+    extend_queries_from_file(f, None, name, opts, None, outf, None)
+}
+
+/// Like `test_queries_from_file`, but plans the statements of `schema` first, plans the workload
+/// as a migration onto `start`, and writes a snapshot after every statement to `steps` if given.
+/// Failures and errors report their line in `f`; those in `schema` have none. A `start` that is
+/// not a valid graph is reported like an unreadable `f`.
+pub fn extend_queries_from_file(f: &Path, schema: Option<&Path>, name: &str, opts: Optimizations,
+                                start: Option<&SavedGraph>, outf: Option<&Path>,
+                                steps: Option<&mut StepWriter>) -> Result<PlanReport, Box<PlanReport>> {
+    let loaded = schema.map_or(Ok(Vec::new()), read_statements)
+                       .and_then(|schema| Ok((schema, read_statements(f)?)))
+                       .and_then(|(schema, statements)| Ok((schema, statements, start.map(SavedGraph::to_graph).transpose()?)));
+    let (schema, statements, start) = match loaded {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            let mut report = plan_queries(&[], &opts);
+            report.errors.push(e);
+            return Err(Box::new(report));
+        }
+    };
+    println!("Loaded {} {} queries", schema.len() + statements.len(), name);
 
     // Try parsing them all
-    let (ok, err) = parse_queries(lines, opts, outf);
+    let lines = schema.iter().chain(statements.iter()).map(|s| s.sql.clone()).collect();
+    let mut report = parse_queries(lines, opts, outf, start, steps);
+    for failure in report.failures.iter_mut() {
+        failure.line = failure.position.checked_sub(schema.len()).map(|i| statements[i].line);
+    }
+    for at in report.errors.iter_mut().filter_map(PlanError::location_mut) {
+        at.line = at.position.checked_sub(schema.len()).map(|i| statements[i].line);
+    }
 
-    println!("Parsing failed: {} queries", err);
-    println!("Parsed successfully: {} queries", ok);
+    println!("Parsing failed: {} queries", report.failures.len());
+    println!("Parsed successfully: {} queries", report.parsed_ok);
+    println!("Skipped: {} queries", report.errors.iter().filter(|e| e.location().is_some()).count());
 
-    if err > 0 {
-        return Err(err);
+    if !report.failures.is_empty() || !report.errors.is_empty() {
+        return Err(Box::new(report));
     }
-    Ok(ok)
+    Ok(report)
+}
+
+/// Plans the workload in `f` under every combination from `Optimizations::all_combinations`,
+/// with the statistics and cost model of `base`.
+pub fn compare_queries_from_file(f: &Path, base: &Optimizations) -> Result<Vec<StrategyResult>, PlanError> {
+    Ok(compare_strategies(&load_queries(f)?, base))
 }
 
 
 #[test]
 fn tpcw_test_queries() {
-    assert!(test_queries_from_file(Path::new("tests/tpc-w-queries.txt"), "TPC-W", Optimizations::new(), None).is_ok());
+    let report = test_queries_from_file(Path::new("tests/tpc-w-queries.txt"), "TPC-W", Optimizations::new(), None).unwrap_err();
+    // getRelated joins item with itself, and the order display query reads address and country
+    // for both the shipping and billing address
+    assert!(report.failures.is_empty());
+    let skipped: Vec<(Option<&str>, &str)> = report.errors.iter().map(|e| match *e {
+        PlanError::SelfJoin { ref at, ref table } => (at.name.as_deref(), table.as_str()),
+        ref other => panic!("unexpected error {}", other),
+    }).collect();
+    assert_eq!(skipped, vec![(Some("getRelated"), "item"), (Some("getMostRecentOrder_order"), "address")]);
 }
 
 #[test]
@@ -91,10 +247,583 @@ fn test_lobsters_schema() {
 
 #[test]
 fn test_long_join() {
-    assert!(test_queries_from_file(Path::new("tests/long-join.txt"), "TPC-W", Optimizations::new(), None).is_ok());
+    let report = test_queries_from_file(Path::new("tests/long-join.txt"), "TPC-W", Optimizations::new(), None).unwrap_err();
+    // the order display query reads address and country for both the shipping and billing address
+    assert!(report.failures.is_empty());
+    assert!(matches!(report.errors[..], [PlanError::SelfJoin { ref table, .. }] if table == "address"));
 }
 
 #[test]
 fn test_combo_join() {
-    assert!(test_queries_from_file(Path::new("tests/combo-join.txt"), "TPC-W", Optimizations::new(), None).is_ok());
+    let report = test_queries_from_file(Path::new("tests/combo-join.txt"), "TPC-W", Optimizations::new(), None).unwrap_err();
+    // the order display query reads address and country for both the shipping and billing address
+    assert!(report.failures.is_empty());
+    assert!(matches!(report.errors[..], [PlanError::SelfJoin { ref table, .. }] if table == "address"));
+}
+
+#[test]
+fn test_query_names() {
+    let queries = load_queries(Path::new("tests/tpc-w-queries.txt")).unwrap();
+    assert!(queries.contains(&String::from("CREATE VIEW getName AS SELECT c_fname,c_lname FROM customer WHERE c_id = ?;")));
+    // doTitleSearch only has commented-out queries, so its name goes unused
+    assert!(!queries.iter().any(|q| q.contains("doTitleSearch")));
+    let report = plan_queries(&queries, &Optimizations::new());
+    let best_sellers = report.query("getBestSellers").unwrap();
+    assert_eq!(report.graph[best_sellers.leaf].data, TestNodeData::Leaf);
+    assert!(report.graph[best_sellers.leaf].users.contains("getBestSellers"));
+
+    // bare SELECTs become views named by position, and failures are reported by name
+    let mut queries = load_queries(Path::new("tests/tpcw-views.txt")).unwrap();
+    queries.push(String::from("SELECT c_id FROM customer;"));
+    queries.push(String::from("CREATE VIEW broken AS SELEKT c_id FROM customer;"));
+    let report = plan_queries(&queries, &Optimizations::new());
+    let leaf = report.query("query_11").unwrap().leaf;
+    assert_eq!(report.graph[leaf].data, TestNodeData::Leaf);
+    assert_eq!(report.tables["query_11"], leaf);
+    assert_eq!(report.failures[0].name.as_deref(), Some("broken"));
+    assert!(report.to_string().contains("failed to parse 'broken': "));
+}
+
+#[test]
+fn test_failures_report_lines() {
+    use std::env;
+    use std::fs;
+
+    let path = env::temp_dir().join(format!("join-tests-lines-{}.txt", std::process::id()));
+    fs::write(&path, "CREATE TABLE a (id int, b_id int);\n\
+                      CREATE TABLE b (id int); -- trailing comment\n\
+                      \n\
+                      # joined\n\
+                      SELECT a.id\n  FROM a, b\n  WHERE a.b_id = b.id;\n\
+                      # broken\n\
+                      SELECT id\n  FRUM a;\n\
+                      SELECT id FROM ghost;\n").unwrap();
+    let report = *test_queries_from_file(&path, "lines", Optimizations::new(), None).unwrap_err();
+    fs::remove_file(&path).unwrap();
+
+    assert!(report.query("joined").is_some());
+    assert_eq!(report.failures.len(), 1);
+    assert_eq!(report.failures[0].name.as_deref(), Some("broken"));
+    assert_eq!(report.failures[0].line, Some(9));
+    assert!(report.to_string().contains("failed to parse 'broken' (line 9): "));
+    assert_eq!(report.errors[0].location().unwrap().line, Some(11));
+    assert!(report.to_string().contains("skipped 'query_5' (line 11): unknown table 'ghost'"));
+}
+
+#[test]
+fn test_noria_recipe() {
+    let schema = Path::new("tests/recipe-schema.txt");
+    let queries = Path::new("tests/recipe-queries.txt");
+    for &strategy in ["pipeline", "prefix", "permutations", "nonprefix", "megajoin"].iter() {
+        let opts = Optimizations { join_strategy: Some(String::from(strategy)), ..Optimizations::new() };
+        let report = extend_queries_from_file(queries, Some(schema), strategy, opts, None, None, None).unwrap();
+        assert_eq!(report.tables.len(), 10);
+        let names: Vec<&str> = report.queries.iter().map(|q| q.label()).collect();
+        assert_eq!(names, vec!["getName", "getCustomer", "customerOrders", "orderLines", "query_10"]);
+
+        let key = |name: &str| -> Vec<String> {
+            let leaf = report.query(name).unwrap().leaf;
+            report.graph[leaf].reader_key.iter().map(|c| c.qualified_name()).collect()
+        };
+        assert_eq!(key("getName"), vec!["customer.c_id"]);
+        assert_eq!(key("getCustomer"), vec!["customer.c_uname"]);
+        assert!(key("customerOrders").is_empty());
+        assert_eq!(key("orderLines"), vec!["orders.o_c_id"]);
+        assert_eq!(key("query_10"), vec!["customer.c_uname", "orders.o_date"]);
+    }
+}
+
+#[test]
+fn test_plan_errors() {
+    let schema = vec![String::from("CREATE TABLE a (id int, b_id int);"),
+                      String::from("CREATE TABLE b (id int, name varchar(10));")];
+    let mut queries = schema.clone();
+    queries.extend(["SELECT id FROM nosuch;",
+                    "CREATE VIEW u AS SELECT a.id FROM a UNION SELECT b.id FROM b;",
+                    "SELECT a.id FROM a JOIN (SELECT b.id FROM b) AS s ON a.b_id = s.id;",
+                    "SELECT id FROM;",
+                    "SELECT x.* FROM a AS x;",
+                    "CREATE VIEW v AS SELECT a.id, b.name FROM a, b WHERE a.b_id = b.id;"].iter().map(|q| q.to_string()));
+    let report = plan_queries(&queries, &Optimizations::new());
+    let at = |name: &str, position: usize| Location { name: Some(String::from(name)), position, line: None };
+    assert_eq!(report.errors, vec![
+        PlanError::UnknownTable { at: at("query_3", 2), table: String::from("nosuch") },
+        PlanError::CompoundSelect { at: at("u", 3) },
+        PlanError::NestedJoin { at: at("query_5", 4) },
+        PlanError::EmptyFrom { at: at("query_6", 5) },
+    ]);
+    assert_eq!(report.errors[0].to_string(), "'query_3': unknown table 'nosuch'");
+    assert!(report.to_string().contains("skipped 'u': compound selects are not supported"));
+    // the skipped statements left nothing behind and the rest was planned
+    assert_eq!(report.parsed_ok, queries.len());
+    let names: Vec<&str> = report.queries.iter().map(|q| q.label()).collect();
+    assert_eq!(names, vec!["query_7", "v"]);
+    let mut planned = schema.clone();
+    planned.extend(queries[6..].iter().cloned());
+    assert_eq!(report.num_nodes(), plan_queries(&planned, &Optimizations::new()).num_nodes());
+
+    let opts = Optimizations { join_strategy: Some(String::from("nosuch")), ..Optimizations::new() };
+    let report = plan_queries(&planned, &opts);
+    assert_eq!(report.errors.len(), 2);
+    assert_eq!(report.errors[1], PlanError::UnknownStrategy { at: at("v", 3), strategy: String::from("nosuch") });
+
+    // two instances of one table cannot be told apart, so a self-join is skipped, not planned
+    let mut self_join = schema.clone();
+    self_join.push(String::from("SELECT a1.id, a2.id FROM a AS a1, a AS a2 WHERE a1.b_id = a2.b_id;"));
+    let report = plan_queries(&self_join, &Optimizations::new());
+    assert_eq!(report.errors, vec![PlanError::SelfJoin { at: at("query_3", 2), table: String::from("a") }]);
+    assert_eq!(report.errors[0].to_string(), "'query_3': self-joins are not supported, 'a' is read more than once");
+    assert!(report.queries.is_empty());
+    assert_eq!(report.num_nodes(), 2);
+
+    // a hand-edited graph with a three-way join
+    let mut report = plan_queries(&queries[..2], &Optimizations::new());
+    let three = report.graph.add_node("join", TestNodeData::InnerJoin, Vec::new(), vec![0, 1, 0], 1);
+    let mut later = queries[7..].to_vec();
+    later.push(String::from("CREATE VIEW names AS SELECT b.name FROM b;"));
+    let report = extend_queries(report.graph, report.tables, &later, &Optimizations::new());
+    // only queries that could reuse the join are affected
+    assert_eq!(report.errors, vec![PlanError::NonBinaryJoin { at: at("v", 0), node: three }]);
+    assert!(report.query("names").is_some());
+
+    match load_queries(Path::new("tests/nosuch.txt")) {
+        Err(PlanError::Io { ref path, .. }) => assert_eq!(path, "tests/nosuch.txt"),
+        other => panic!("expected an I/O error, got {:?}", other),
+    }
+    let report = *test_queries_from_file(Path::new("tests/nosuch.txt"), "missing", Optimizations::new(), None).unwrap_err();
+    assert!(report.errors[0].location().is_none());
+
+    // a graph that cannot be written is an error, but the workload is still planned
+    let outf = Path::new("/nonexistent/dir/combo");
+    let report = *test_queries_from_file(Path::new("tests/combo-join.txt"), "combo", Optimizations::new(), Some(outf)).unwrap_err();
+    assert!(matches!(report.errors[..], [PlanError::SelfJoin { .. }, PlanError::Write { ref path, .. }] if path == "/nonexistent/dir/combo"));
+    assert_eq!(report.queries.len(), 8);
+}
+
+#[test]
+fn test_resolve_columns() {
+    let mut queries = vec![String::from("CREATE TABLE a (id int, name varchar(10), total int);"),
+                           String::from("CREATE TABLE b (id int, a_id int, nme varchar(10));")];
+    queries.extend(["SELECT nmae FROM a;",
+                    "SELECT id FROM a, b WHERE a.id = b.a_id;",
+                    "SELECT x.nam FROM a AS x;",
+                    "SELECT c.id FROM a;",
+                    "SELECT a.total - b.a_id AS d FROM a, b WHERE d >= 0 AND a.id = b.a_id ORDER BY d;",
+                    "SELECT a.name FROM a WHERE a.id IN (SELECT b.a_id FROM b WHERE b.nme = a.name);",
+                    "CREATE VIEW v AS SELECT x.name, count(*) AS n FROM a AS x JOIN b ON x.id = a_id GROUP BY x.name;",
+                    "SELECT v.name FROM v WHERE n > 1;",
+                    "SELECT a.name FROM a, b WHERE a.id = b.a_id AND a.id = ? AND b.id = ?;"].iter().map(|q| q.to_string()));
+    let report = plan_queries(&queries, &Optimizations::new());
+    let errors: Vec<String> = report.errors.iter().map(|e| e.to_string()).collect();
+    assert_eq!(errors, vec![
+        "'query_3': unknown column 'nmae'; did you mean a.name?",
+        "'query_4': column 'id' is ambiguous, it could be a.id or b.id",
+        "'query_5': unknown column 'x.nam'; did you mean x.name?",
+        "'query_6': unknown column 'c.id'; did you mean a.id?",
+    ]);
+    let names: Vec<&str> = report.queries.iter().map(|q| q.label()).collect();
+    assert_eq!(names, vec!["query_7", "query_8", "v", "query_10", "query_11"]);
+    // parameters on same-named columns key the reader on both
+    let leaf = report.query("query_11").unwrap().leaf;
+    let key: Vec<String> = report.graph[leaf].reader_key.iter().map(|c| c.qualified_name()).collect();
+    assert_eq!(key, vec!["a.id", "b.id"]);
+
+    let join = report.graph.iter().find(|n| n.is_join()).unwrap();
+    let keys: Vec<String> = join.join_keys.iter().map(|p| p.to_string()).collect();
+    assert_eq!(keys, vec!["a.id = b.a_id"]);
+
+    let select = match nom_sql::parse_query(&queries[8]).unwrap() {
+        nom_sql::SqlQuery::CreateView(view) => match *view.definition {
+            nom_sql::SelectSpecification::Simple(s) => s,
+            _ => unreachable!(),
+        },
+        _ => unreachable!(),
+    };
+    let resolution = resolve_select(&select, &report.tables, &report.graph, &Location::default()).unwrap();
+    let columns: Vec<String> = resolution.columns().iter().map(|c| c.to_string()).collect();
+    assert_eq!(columns, vec!["b.a_id", "x.id", "x.name"]);
+    assert_eq!(resolution.relation("x").map(|r| r.table.as_str()), Some("a"));
+    assert_eq!(resolution.output_aliases, vec!["n"]);
+    // join predicates are qualified with the relation instance, aliases included
+    let keys: Vec<String> = resolution.qualify_predicates(&join_predicates(&select)).iter().map(|p| p.to_string()).collect();
+    assert_eq!(keys, vec!["x.id = b.a_id"]);
+}
+
+#[test]
+fn test_column_lineage() {
+    let queries: Vec<String> = [
+        "CREATE TABLE customer (c_id int NOT NULL, c_name varchar(20), PRIMARY KEY (c_id));",
+        "CREATE TABLE orders (o_id int, c_id int, total int, PRIMARY KEY (o_id));",
+        "CREATE VIEW both AS SELECT * FROM orders JOIN customer ON orders.c_id = customer.c_id;",
+        "CREATE VIEW spent AS SELECT c.c_name, sum(o.total) AS amount, count(*) AS n FROM customer AS c \
+         JOIN orders AS o ON c.c_id = o.c_id GROUP BY c.c_name;",
+        "SELECT spent.amount AS a FROM spent;",
+    ].iter().map(|q| q.to_string()).collect();
+    let report = plan_queries(&queries, &Optimizations::new());
+    assert!(report.errors.is_empty() && report.failures.is_empty());
+    let project = |name: &str| &report.graph[report.graph[report.query(name).unwrap().leaf].ancestors[0]];
+    let describe = |c: &Column| {
+        let lineage: Vec<String> = c.lineage.iter().map(|b| b.to_string()).collect();
+        format!("{} {} {:?} {} [{}]", c.qualified_name(), c.output_name(), c.sql_type, c.nullable, lineage.join(","))
+    };
+
+    // the equi-joined c_id is output once, and a key is never NULL after an inner join
+    let columns: Vec<String> = project("both").columns.iter().map(describe).collect();
+    assert_eq!(columns, vec![
+        "orders.o_id o_id Some(\"INT(32)\") false [orders.o_id]",
+        "orders.c_id c_id Some(\"INT(32)\") false [customer.c_id,orders.c_id]",
+        "orders.total total Some(\"INT(32)\") true [orders.total]",
+        "customer.c_name c_name Some(\"VARCHAR(20)\") true [customer.c_name]",
+    ]);
+
+    // aggregates are computed from their argument's base columns
+    let columns: Vec<String> = project("spent").columns.iter().map(describe).collect();
+    assert_eq!(columns, vec![
+        "customer.c_name c_name Some(\"VARCHAR(20)\") true [customer.c_name]",
+        "amount amount None true [orders.total]",
+        "n n None false []",
+    ]);
+    // megajoin outer-joins everything, so only the first input's key stays NOT NULL
+    let megajoin = plan_queries(&queries, &Optimizations { megajoin: true, ..Optimizations::new() });
+    let join = megajoin.graph.iter().find(|n| n.data == TestNodeData::OuterJoin).unwrap();
+    let nullable: Vec<(String, bool)> = join.columns.iter().map(|c| (c.qualified_name(), c.nullable)).collect();
+    assert_eq!(nullable, vec![(String::from("orders.o_id"), false), (String::from("orders.c_id"), true),
+                              (String::from("orders.total"), true), (String::from("customer.c_id"), true),
+                              (String::from("customer.c_name"), true)]);
+
+    // a view's columns are read as columns of the view, with their lineage
+    let columns: Vec<String> = project("query_5").columns.iter().map(describe).collect();
+    assert_eq!(columns, vec!["spent.amount a None true [orders.total]"]);
+
+    // a key the query states twice, once each way round, is one key of the join
+    let mut restated = queries[..2].to_vec();
+    restated.push(String::from("SELECT o_id FROM orders, customer WHERE orders.c_id = customer.c_id AND customer.c_id = orders.c_id;"));
+    let report = plan_queries(&restated, &Optimizations::new());
+    let join = report.graph.iter().find(|n| n.is_join()).unwrap();
+    let keys: Vec<String> = join.join_keys.iter().map(|p| p.to_string()).collect();
+    assert_eq!(keys, vec!["orders.c_id = customer.c_id"]);
+}
+
+#[test]
+fn test_expression_projections() {
+    let queries: Vec<String> = [
+        "CREATE TABLE address (addr_id int NOT NULL, addr_street1 varchar(40), addr_zip int NOT NULL);",
+        "CREATE TABLE orders (o_id int NOT NULL, o_ship_id int NOT NULL, total int NOT NULL, discount int);",
+        "SELECT ship.addr_street1 AS ship_addr_street1, o.total * 2 AS doubled, o.total - o.discount, \
+         1 AS one, max(o.total) AS biggest FROM orders AS o, address AS ship WHERE o.o_ship_id = ship.addr_id;",
+    ].iter().map(|q| q.to_string()).collect();
+    let report = plan_queries(&queries, &Optimizations::new());
+    assert!(report.errors.is_empty() && report.failures.is_empty());
+    let project = &report.graph[report.graph[report.queries[0].leaf].ancestors[0]];
+    let columns: Vec<String> = project.columns.iter().map(|c| {
+        let lineage: Vec<String> = c.lineage.iter().map(|b| b.to_string()).collect();
+        format!("{} <- [{}] [{}] {}", c.output_name(), c.inputs.join(","), lineage.join(","), c.nullable)
+    }).collect();
+    assert_eq!(columns, vec![
+        "ship_addr_street1 <- [] [address.addr_street1] true",
+        "doubled <- [orders.total] [orders.total] false",
+        "o.total - o.discount <- [orders.total,orders.discount] [orders.discount,orders.total] true",
+        "one <- [] [] false",
+        "biggest <- [orders.total] [orders.total] true",
+    ]);
+    assert_eq!(project.columns[0].name, "addr_street1");
+
+    // the operands of the expressions are carried through the join, the zip code is not
+    let join = &report.graph[project.ancestors[0]];
+    let carried: Vec<String> = join.columns.iter().map(|c| c.qualified_name()).collect();
+    assert_eq!(carried, vec!["orders.o_ship_id", "orders.total", "orders.discount", "address.addr_id", "address.addr_street1"]);
+}
+
+#[test]
+fn test_column_pruning() {
+    let queries: Vec<String> = [
+        "CREATE TABLE customer (c_id int, c_name varchar(20), c_email varchar(40), c_phone varchar(20));",
+        "CREATE TABLE orders (o_id int, c_id int, total int, o_date date);",
+        "CREATE VIEW names AS SELECT customer.c_name FROM customer, orders WHERE customer.c_id = orders.c_id;",
+        "CREATE VIEW totals AS SELECT c_email, sum(total) AS spent FROM customer, orders \
+         WHERE customer.c_id = orders.c_id GROUP BY c_email;",
+        "SELECT c_phone FROM customer WHERE c_id = ?;",
+    ].iter().map(|q| q.to_string()).collect();
+    let report = plan_queries(&queries, &Optimizations::new());
+    let names = |n: NodeIndex| -> Vec<String> { report.graph[n].columns.iter().map(|c| c.qualified_name()).collect() };
+    let projection = |table: &str| *report.graph[report.tables[table]].children.first().unwrap();
+
+    // the shared join and projections carry what either view reads, and nothing else
+    let join = report.query("totals").unwrap().reused_nodes.iter().cloned().find(|&n| report.graph[n].is_join()).unwrap();
+    assert_eq!(names(join), vec!["customer.c_id", "customer.c_name", "customer.c_email", "orders.total"]);
+    assert_eq!(names(projection("orders")), vec!["orders.c_id", "orders.total"]);
+    assert_eq!(names(projection("customer")), vec!["customer.c_id", "customer.c_name", "customer.c_email",
+                                                   "customer.c_phone"]);
+    assert_eq!(report.graph[projection("customer")].children.len(), 2);
+    let unpruned = report.graph[join].maxrows * 8 * BYTES_PER_COLUMN;
+    assert!(report.graph[join].estimated_bytes() < unpruned);
+}
+
+#[test]
+fn test_queries_over_views() {
+    let queries: Vec<String> = [
+        "CREATE TABLE a (id int, x int);",
+        "CREATE TABLE b (id int, a_id int, y int);",
+        "CREATE TABLE c (id int, b_id int, z int);",
+        "CREATE VIEW abc AS SELECT a.x, c.z FROM a, b, c WHERE a.id = b.a_id AND b.id = c.b_id;",
+        "CREATE VIEW ab AS SELECT a.x, b.id FROM a, b WHERE a.id = b.a_id;",
+        "CREATE VIEW abc_again AS SELECT ab.x, c.z FROM ab, c WHERE ab.id = c.b_id;",
+        "CREATE VIEW ab_x AS SELECT ab.x FROM ab;",
+        "CREATE VIEW counts AS SELECT b.a_id, count(*) AS n FROM b GROUP BY b.a_id;",
+        "CREATE VIEW counted AS SELECT counts.n, a.x FROM counts, a WHERE counts.a_id = a.id;",
+    ].iter().map(|q| q.to_string()).collect();
+    let report = plan_queries(&queries, &Optimizations::new());
+    assert!(report.errors.is_empty());
+    let query = |name: &str| report.query(name).unwrap();
+    let joins = |q: &QueryResult| q.new_nodes.iter().filter(|&&n| report.graph[n].is_join()).count();
+    let project = |name: &str| &report.graph[report.graph[query(name).leaf].ancestors[0]];
+
+    // taking `ab` apart shares all of abc's joins
+    assert_eq!(joins(query("abc_again")), 0);
+    let abc_join = project("abc").ancestors[0];
+    assert_eq!(project("abc_again").ancestors, vec![abc_join]);
+    let columns: Vec<String> = project("abc_again").columns.iter().map(|c| c.output_name().to_string()).collect();
+    assert_eq!(columns, vec!["x", "z"]);
+
+    // reading the view whole is as cheap as anything, so its leaf is kept
+    assert_eq!(project("ab_x").ancestors, vec![report.tables["ab"]]);
+
+    // an aggregating view cannot be taken apart and is joined as a relation
+    let join = project("counted").ancestors[0];
+    assert!(report.graph[join].is_join());
+    assert!(report.graph[join].ancestors.contains(&report.tables["counts"]));
+    let ancestors = join::get_all_ancestors(join, &report.graph);
+    assert!(ancestors.contains(&String::from("counts")));
+    assert!(!ancestors.contains(&String::from("b")));
+}
+
+#[test]
+fn test_plan_report_combo_join() {
+    let report = *test_queries_from_file(Path::new("tests/combo-join.txt"), "combo", Optimizations::new(), None).unwrap_err();
+    assert_eq!(report.errors.len(), 1);
+    assert_eq!(report.queries.len(), 8);
+    assert!(report.queries[0].reused_nodes.is_empty());
+    assert_eq!(report.queries[0].new_nodes.last(), Some(&report.queries[0].leaf));
+    let total_new: usize = report.queries.iter().map(|q| q.new_nodes.len()).sum();
+    assert_eq!(total_new + 8, report.num_nodes());
+    assert!(report.total_reused() > 0);
+}
+
+#[test]
+fn test_migration_reuses_saved_graph() {
+    let queries = load_queries(Path::new("tests/combo-join.txt")).unwrap();
+    let (before, after) = queries.split_at(10);  // the schema and the first two queries
+    let opts = Optimizations::new();
+
+    let first = plan_queries(before, &opts);
+    let saved = SavedGraph::from_json(&first.saved_graph().to_json()).unwrap();
+    let (graph, tables) = saved.to_graph().unwrap();
+    let migrated = extend_queries(graph, tables, after, &opts);
+
+    // migrating in two steps should give the same graph as planning everything at once
+    let all_at_once = plan_queries(&queries, &opts);
+    assert_eq!(migrated.num_nodes(), all_at_once.num_nodes());
+    assert_eq!(migrated.first_new, first.num_nodes());
+    assert_eq!(migrated.queries.len(), 7);
+    // customer, orders, order_line, item reuses the customer/orders join from before
+    assert!(migrated.queries[1].reused_nodes.iter().any(|&i| i < migrated.first_new));
+    assert!(migrated.migration_plan().starts_with(&format!("MIGRATION: {} existing nodes", first.num_nodes())));
+}
+
+#[test]
+fn test_remove_views_frees_unused_nodes() {
+    let mut queries = load_queries(Path::new("tests/tpcw-views.txt")).unwrap();
+    queries.push(String::from("DROP VIEW b;"));
+    let mut report = plan_queries(&queries, &Optimizations::new());
+
+    // b reused a's join, so dropping it frees only its own join, projection and leaf, and the
+    // projection of order_line that nothing else reads
+    assert_eq!(report.removals.len(), 1);
+    assert_eq!(report.removals[0].freed_nodes.len(), 4);
+    assert_eq!(report.queries.len(), 1);
+    assert_eq!(report.num_nodes(), 8 + 5);
+    assert!(!report.tables.contains_key("b"));
+    for (i, node) in report.graph.iter().enumerate() {
+        assert_eq!(node.index, i);
+        assert!(node.ancestors.iter().chain(node.children.iter()).all(|&n| n < report.num_nodes()));
+    }
+
+    let freed = report.remove_query("a").unwrap();
+    assert_eq!(freed.freed_nodes.len(), 5);
+    assert!(freed.freed_bytes > 0);
+    assert_eq!(report.num_nodes(), 8);
+    assert!(report.graph.iter().all(|n| n.users.is_empty() && n.children.is_empty()));
+    assert!(report.tables.iter().all(|(name, &n)| report.graph[n].name == *name));
+}
+
+#[test]
+fn test_planning_on_many_threads() {
+    use std::thread;
+
+    let queries = load_queries(Path::new("tests/tpc-w-queries.txt")).unwrap();
+    let combinations = Optimizations::all_combinations();
+    let summary = |r: &PlanReport| (r.num_nodes(), r.num_joins(), r.total_reused());
+    let serial: Vec<(usize, usize, usize)> = combinations.iter().map(|opts| summary(&plan_queries(&queries, opts))).collect();
+    let handles: Vec<thread::JoinHandle<PlanReport>> = combinations
+        .into_iter()
+        .map(|opts| {
+            let queries = queries.clone();
+            thread::spawn(move || plan_queries(&queries, &opts))
+        })
+        .collect();
+    let reports: Vec<PlanReport> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+    assert!(reports.iter().all(|r| r.failures.is_empty()));
+    // every thread plans exactly what planning the same combination alone does
+    let concurrent: Vec<(usize, usize, usize)> = reports.iter().map(summary).collect();
+    assert_eq!(concurrent, serial);
+}
+
+#[test]
+fn test_custom_join_strategy() {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    /// Joins the relations in reverse order and never reuses anything.
+    struct Reversed;
+
+    impl JoinStrategy for Reversed {
+        fn name(&self) -> &str {
+            "test-reversed"
+        }
+
+        fn plan(&self, relations: &[String], predicates: &[JoinPredicate], catalog: &HashMap<String, NodeIndex>,
+                graph: &mut Graph, _opts: &Optimizations) -> NodeIndex {
+            assert!(!predicates.is_empty());
+            let mut nodes = relations.iter().rev().map(|r| catalog[r]);
+            let first = nodes.next().unwrap();
+            nodes.fold(first, |prev, next| {
+                let columns = graph[prev].columns.iter().chain(graph[next].columns.iter()).cloned().collect();
+                let maxrows = graph[prev].maxrows.min(graph[next].maxrows);
+                graph.add_node("join", TestNodeData::InnerJoin, columns, vec![prev, next], maxrows)
+            })
+        }
+    }
+
+    register_strategy(Arc::new(Reversed));
+    assert!(strategy_names().contains(&String::from("test-reversed")));
+    let opts = Optimizations { join_strategy: Some(String::from("test-reversed")), ..Optimizations::new() };
+    let report = plan_queries(&load_queries(Path::new("tests/combo-join.txt")).unwrap(), &opts);
+    assert!(report.failures.is_empty());
+    // only the tables' narrow projections are shared
+    assert!(report.queries.iter().flat_map(|q| q.reused_nodes.iter()).all(|&n| report.graph[n].data == TestNodeData::Project));
+    // the first query joins customer and orders, so reversed the join's ancestors are orders, customer
+    let project = report.graph[report.queries[0].leaf].ancestors[0];
+    let join = &report.graph[report.graph[project].ancestors[0]];
+    let inputs: Vec<&str> = join.ancestors.iter().map(|&a| report.graph[a].name.as_str()).collect();
+    assert_eq!(inputs, vec!["orders", "customer"]);
+}
+
+#[test]
+fn test_prefix_joins_follow_predicates() {
+    let mut queries: Vec<String> = load_queries(Path::new("tests/tpcw-views.txt")).unwrap()[..8].to_vec();
+    queries.push(String::from("SELECT * FROM customer, order_line, orders \
+                               WHERE customer.c_id = orders.o_c_id AND orders.o_id = order_line.ol_o_id;"));
+    let opts = Optimizations { join_strategy: Some(String::from("prefix")), ..Optimizations::new() };
+    let report = plan_queries(&queries, &opts);
+    assert!(report.failures.is_empty() && report.errors.is_empty());
+    // customer and order_line share no key, so orders is joined in before order_line
+    let project = report.graph[report.queries[0].leaf].ancestors[0];
+    let outer = &report.graph[report.graph[project].ancestors[0]];
+    let inner = &report.graph[outer.ancestors[0]];
+    assert_eq!(report.graph[outer.ancestors[1]].name, "order_line");
+    let inputs: Vec<&str> = inner.ancestors.iter().map(|&a| report.graph[a].name.as_str()).collect();
+    assert_eq!(inputs, vec!["customer", "orders"]);
+    assert!(!inner.join_keys.is_empty() && !outer.join_keys.is_empty());
+
+    // the same under aliases, which reuses both joins
+    queries.push(String::from("SELECT * FROM customer AS c, order_line AS ol, orders AS o \
+                               WHERE c.c_id = o.o_c_id AND o.o_id = ol.ol_o_id;"));
+    let report = plan_queries(&queries, &opts);
+    assert!(report.failures.is_empty() && report.errors.is_empty());
+    let aliased = &report.queries[1];
+    assert!(aliased.new_nodes.iter().all(|&n| !report.graph[n].is_join()));
+    assert!(aliased.reused_nodes.contains(&inner.index) && aliased.reused_nodes.contains(&outer.index));
+}
+
+#[test]
+fn test_pipeline_matches_flags() {
+    let queries = load_queries(Path::new("tests/combo-join.txt")).unwrap();
+    for opts in Optimizations::all_combinations().into_iter().filter(|o| o.passes.is_none()) {
+        let by_flags = plan_queries(&queries, &opts);
+        let explicit = Optimizations::with_passes(opts.pipeline().passes);
+        let by_passes = plan_queries(&queries, &explicit);
+        assert_eq!((by_flags.num_nodes(), by_flags.total_reused()), (by_passes.num_nodes(), by_passes.total_reused()),
+                   "{} vs {}", opts.label(), explicit.label());
+    }
+
+    let mut pipeline: Pipeline = "match-reuse, canonicalize-names".parse().unwrap();
+    assert_eq!(pipeline.passes, vec![Pass::MatchReuse, Pass::CanonicalizeNames]);
+    pipeline.disable(Pass::CanonicalizeNames);
+    pipeline.enable(Pass::MatchSubsets);
+    pipeline.enable(Pass::ChooseJoinOrder);
+    assert_eq!(pipeline.label(), "match-subsets>choose-join-order>match-reuse");
+    assert!("match-everything".parse::<Pipeline>().is_err());
+}
+
+#[test]
+fn test_workload_stats() {
+    let queries = load_queries(Path::new("tests/tpc-w-queries.txt")).unwrap();
+    let stats = workload_stats(&queries, &Optimizations::new());
+    assert_eq!(stats.statements, queries.len());
+    assert_eq!(stats.tables + stats.views + stats.selects + stats.drops + stats.other + stats.failures, stats.statements);
+    assert_eq!(stats.tables, 10);
+    assert_eq!(stats.table_rows["customer"], 2880000);
+    assert_eq!(stats.relations_per_query.len(), stats.views + stats.selects);
+    assert_eq!(stats.max_relations(), 7);
+    assert_eq!(stats.nodes, plan_queries(&queries, &Optimizations::new()).num_nodes());
+}
+
+#[test]
+fn test_explain_view() {
+    let queries = load_queries(Path::new("tests/tpcw-views.txt")).unwrap();
+    let report = plan_queries(&queries, &Optimizations::new());
+
+    let explanation = report.explain("b").unwrap();
+    assert!(explanation.starts_with("QUERY b\n  sql: CREATE VIEW b"));
+    assert!(explanation.contains("join:InnerJoin [2592000 rows, reused, shared with a]"));
+    assert!(explanation.contains("order_line:Base [7775551 rows, base]"));
+    assert_eq!(explanation.matches(", new]").count(), 4);
+    assert!(report.explain("c").is_none());
+}
+
+#[test]
+fn test_sharing_metrics() {
+    let queries = load_queries(Path::new("tests/tpcw-views.txt")).unwrap();
+    let report = plan_queries(&queries, &Optimizations::new());
+
+    // a adds projections of customer and orders, a join, a projection and its leaf; b reuses the
+    // first three and adds a projection of order_line and three more nodes
+    let factors = report.sharing_factors();
+    assert_eq!(factors.len(), 9);
+    let b = report.query("b").unwrap();
+    let join = *b.reused_nodes.iter().find(|&&n| report.graph[n].is_join()).unwrap();
+    assert!(factors.contains(&(join, 2)));
+    assert!((report.shared_fraction() - 3.0 / 9.0).abs() < 1e-9);
+    assert!((report.mean_sharing() - 12.0 / 9.0).abs() < 1e-9);
+    assert_eq!(report.query("a").unwrap().reuse_fraction(), 0.0);
+    assert!((b.reuse_fraction() - 3.0 / 7.0).abs() < 1e-9);
+
+    let text = report.to_string();
+    assert!(text.contains("planned 'b': 4 new nodes, 3 reused (43% reused)"));
+    assert!(text.contains(&format!("node n{} join: used by 2 queries", join)));
+    assert!(text.contains("SHARED_NODES: 33.3%\nMEAN_SHARING: 1.33"));
+}
+
+#[test]
+fn test_compare_combo_join() {
+    let results = compare_queries_from_file(Path::new("tests/combo-join.txt"), &Optimizations::new()).unwrap();
+    assert_eq!(results.len(), Optimizations::all_combinations().len());
+    let megajoin = results.iter().find(|r| r.strategy == "megajoin").unwrap();
+    assert_eq!(megajoin.joins, megajoin.outer_joins);
+    let plain = results.iter().find(|r| r.strategy == "none").unwrap();
+    let overlap = results.iter().find(|r| r.strategy == "overlap").unwrap();
+    // without overlap only the tables' narrow projections are shared
+    assert!(overlap.reused > plain.reused);
+    assert!(overlap.shared > plain.shared);
+    assert!(overlap.nodes < plain.nodes);
 }
@@ -1,72 +1,468 @@
 extern crate clap;
 extern crate join_tests;
 
-use clap::{Arg, App};
-use join_tests::Optimizations;
-use join_tests::test_queries_from_file;
+use clap::{Arg, ArgMatches, App, AppSettings, SubCommand};
+use join_tests::{Config, Graph, Optimizations, OutputConfig, PlanReport, QueryResult, SavedGraph, StepWriter};
+use join_tests::{extend_queries, extend_queries_from_file, format_comparison, graphviz_with, load_queries, plan_queries};
+use join_tests::{compare_strategies, render_with_fallback, workload_stats};
 use std::path::Path;
 use std::fs::File;
-use std::process::Command;
+use std::io::{self, Write};
+use std::process;
 
 
+/// Exit code when the workload has statements that fail to parse or cannot be planned, or the
+/// query to explain does not exist.
+const EXIT_PLAN_FAILED: i32 = 1;
+/// Exit code for bad arguments or config, unreadable or unwritable files, and renderer failures.
+const EXIT_ERROR: i32 = 2;
+
+struct CliError {
+    code: i32,
+    message: String,
+}
+
+impl CliError {
+    fn plan(message: String) -> Self {
+        CliError { code: EXIT_PLAN_FAILED, message }
+    }
+
+    fn other(message: String) -> Self {
+        CliError { code: EXIT_ERROR, message }
+    }
+}
+
+
+fn input_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("INPUT")
+        .help("Sets the input file to use")
+        .required_unless("CONFIG")
+        .index(1)
+}
+
+fn schema_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("SCHEMA")
+        .help("File of CREATE TABLE statements to plan before the input, such as a recipe's schema")
+        .takes_value(true)
+        .long("schema")
+}
+
+fn config_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("CONFIG")
+        .help("TOML file with planner, statistics and output settings; flags override it")
+        .takes_value(true)
+        .short("c")
+        .long("config")
+}
+
+/// Arguments shared by every subcommand that plans a workload.
+fn planner_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![input_arg(),
+         schema_arg(),
+         config_arg(),
+         Arg::with_name("OVERLAP")
+             .help("Whether to attempt to reuse overlap with previous queries")
+             .short("o")
+             .long("overlap"),
+         Arg::with_name("PERMUTATIONS")
+             .help("Whether to try all permutations of joined tables when looking for overlap")
+             .short("p")
+             .long("permutations"),
+         Arg::with_name("SORTED")
+             .help("Whether to sort names of joined tables to aid in overlap")
+             .short("s")
+             .long("sorted"),
+         Arg::with_name("NONPREFIX")
+             .help("Whether to look for overlap in a non-topological way")
+             .short("n")
+             .long("nonprefix"),
+         Arg::with_name("MEGAJOIN")
+             .help("Whether to outer-join everything to maximize overlap")
+             .short("m")
+             .long("megajoin"),
+         Arg::with_name("PASSES")
+             .help("Comma-separated planning passes to run instead of the ones the flags select: \
+                    canonicalize-names, match-subsets, choose-join-order, match-reuse, expand-megajoin")
+             .takes_value(true)
+             .long("passes"),
+         Arg::with_name("STRATEGY")
+             .help("Join strategy to use instead of the pass pipeline")
+             .takes_value(true)
+             .possible_values(&["pipeline", "prefix", "permutations", "nonprefix", "megajoin"])
+             .long("strategy")]
+}
+
+fn from_graph_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("FROM")
+        .help("Plan the input as a migration onto a graph saved with --save-graph")
+        .takes_value(true)
+        .long("from-graph")
+}
+
+fn render_format_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("FORMAT")
+        .help("Comma-separated formats to write the graph in, e.g. pdf,svg,mermaid; dot, graphml, json and mermaid are \
+               exported without a renderer [default: pdf]")
+        .takes_value(true)
+        .long("format")
+}
+
+fn renderer_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("RENDERER")
+        .help("Graphviz program to render with, or native for the built-in SVG layout [default: dot]")
+        .takes_value(true)
+        .long("renderer")
+}
+
+fn clustered_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("CLUSTERED")
+        .help("Draw each query's nodes in a cluster of their own")
+        .long("clustered")
+}
 
 fn main() {
-    let matches = App::new("Join planning strategy tests")
-                        .version("0.1")
-                        .arg(Arg::with_name("INPUT")
-                                .help("Sets the input file to use")
-                                .required(true)
-                                .index(1))
-                        .arg(Arg::with_name("LABEL")
-                                .help("Label to pass to test_queries_from_file")
-                                .takes_value(true)
-                                .short("l")
-                                .long("label"))
-                        .arg(Arg::with_name("OUTPUT")
-                                .help("Output file for graphviz")
-                                .takes_value(true)
-                                .short("f")
-                                .long("output"))
-                        .arg(Arg::with_name("OVERLAP")
-                                .help("Whether to attempt to reuse overlap with previous queries")
-                                .short("o")
-                                .long("overlap"))
-                        .arg(Arg::with_name("PERMUTATIONS")
-                                .help("Whether to try all permutations of joined tables when looking for overlap")
-                                .short("p")
-                                .long("permutations"))
-                        .arg(Arg::with_name("SORTED")
-                                .help("Whether to sort names of joined tables to aid in overlap")
-                                .short("s")
-                                .long("sorted"))
-                        .arg(Arg::with_name("NONPREFIX")
-                                .help("Whether to look for overlap in a non-topological way")
-                                .short("n")
-                                .long("nonprefix"))
-                        .arg(Arg::with_name("MEGAJOIN")
-                                .help("Whether to outer-join everything to maximize overlap")
-                                .short("m")
-                                .long("megajoin"))
-                        .get_matches();
-
-    let file = Path::new(matches.value_of("INPUT").unwrap());
-    let label = matches.value_of("LABEL").unwrap_or("undefined");
-    let out_name = matches.value_of("OUTPUT").unwrap_or("graph");
-    let graph_out_name = format!("{}.pdf", out_name);
-    let output_file = Path::new(out_name);
-    let graph_file = Path::new(&graph_out_name);
-
-    let overlap = matches.is_present("OVERLAP");
-    let permutations = matches.is_present("PERMUTATIONS");
-    let sorted_names = matches.is_present("SORTED");
-    let nonprefix = matches.is_present("NONPREFIX");
-    let megajoin = matches.is_present("MEGAJOIN");
-    let opts = Optimizations{overlap, permutations, sorted_names, nonprefix, megajoin};
-
-    test_queries_from_file(file, label, opts, Some(output_file)).expect("Testing queries failed!");
-    let output = Command::new("dot")
-                         .arg("-Tpdf")
-                         .stdin(File::open(output_file).unwrap())
-                         .stdout(File::create(graph_file).unwrap())
-                         .spawn();
+    let app = App::new("Join planning strategy tests")
+        .version("0.1")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(SubCommand::with_name("plan")
+                .about("Plans the input, prints the report and writes and renders the graph")
+                .args(&planner_args())
+                .arg(from_graph_arg())
+                .arg(Arg::with_name("LABEL")
+                        .help("Label to pass to test_queries_from_file")
+                        .takes_value(true)
+                        .short("l")
+                        .long("label"))
+                .arg(Arg::with_name("OUTPUT")
+                        .help("Output file for graphviz")
+                        .takes_value(true)
+                        .short("f")
+                        .long("output"))
+                .arg(Arg::with_name("SAVE")
+                        .help("Also write the planned graph as JSON to this file")
+                        .takes_value(true)
+                        .short("g")
+                        .long("save-graph"))
+                .arg(render_format_arg())
+                .arg(renderer_arg())
+                .arg(clustered_arg())
+                .arg(Arg::with_name("STEPS")
+                        .help("Also write the graph after every statement to <output>-step-NNN.<format>, \
+                               indexed in <output>-steps.tsv")
+                        .long("steps")))
+        .subcommand(SubCommand::with_name("compare")
+                .about("Plans the input under every optimization combination and prints one table")
+                .arg(input_arg())
+                .arg(schema_arg())
+                .arg(config_arg())
+                .arg(Arg::with_name("FORMAT")
+                        .help("Output format for the comparison table [default: text]")
+                        .takes_value(true)
+                        .possible_values(&["text", "csv", "json"])
+                        .long("format")))
+        .subcommand(SubCommand::with_name("render")
+                .about("Renders a graph saved with --save-graph")
+                .arg(Arg::with_name("GRAPH")
+                        .help("Saved graph to render")
+                        .required(true)
+                        .index(1))
+                .arg(config_arg())
+                .arg(Arg::with_name("OUTPUT")
+                        .help("Output file name, without extension [default: the saved graph's name]")
+                        .takes_value(true)
+                        .short("f")
+                        .long("output"))
+                .arg(render_format_arg())
+                .arg(renderer_arg())
+                .arg(clustered_arg()))
+        .subcommand(SubCommand::with_name("stats")
+                .about("Summarizes the statements in the input and what planning them produces")
+                .args(&planner_args()))
+        .subcommand(SubCommand::with_name("explain")
+                .about("Shows how one query was planned and which nodes it reuses")
+                .args(&planner_args())
+                .arg(from_graph_arg())
+                .arg(Arg::with_name("QUERY")
+                        .help("View name of the query, or query_<n> for the unnamed SELECT at position n")
+                        .takes_value(true)
+                        .required(true)
+                        .short("q")
+                        .long("query")));
+
+    let matches = match app.get_matches_safe() {
+        Ok(matches) => matches,
+        Err(e) => {
+            if !e.use_stderr() {
+                e.exit();
+            }
+            eprintln!("{}", e.message);
+            process::exit(EXIT_ERROR);
+        }
+    };
+
+    let result = match matches.subcommand() {
+        ("plan", Some(m)) => plan(m),
+        ("compare", Some(m)) => compare(m),
+        ("render", Some(m)) => render(m),
+        ("stats", Some(m)) => stats(m),
+        ("explain", Some(m)) => explain(m),
+        _ => unreachable!(),
+    };
+    if let Err(e) = result {
+        eprintln!("error: {}", e.message);
+        process::exit(e.code);
+    }
+}
+
+fn load_config(matches: &ArgMatches) -> Result<Config, CliError> {
+    match matches.value_of("CONFIG") {
+        Some(path) => Config::load(Path::new(path))
+                          .map_err(|e| CliError::other(format!("loading config {} failed: {}", path, e))),
+        None => Ok(Config::default()),
+    }
+}
+
+/// The input file named on the command line, or else the one in the config.
+fn input_file(matches: &ArgMatches, config: &Config) -> Result<String, CliError> {
+    matches.value_of("INPUT")
+           .map(String::from)
+           .or_else(|| config.input.clone())
+           .ok_or_else(|| CliError::other(String::from("no input file given on the command line or in the config")))
+}
+
+/// The schema file named on the command line, or else the one in the config, if any.
+fn schema_file(matches: &ArgMatches, config: &Config) -> Result<Option<String>, CliError> {
+    let schema = matches.value_of("SCHEMA").map(String::from).or_else(|| config.schema.clone());
+    match schema {
+        Some(ref path) if !Path::new(path).is_file() => Err(CliError::other(format!("cannot read schema file {}", path))),
+        schema => Ok(schema),
+    }
+}
+
+/// The statements of the schema file, if any, followed by those of the input.
+fn load_input(matches: &ArgMatches, config: &Config) -> Result<Vec<String>, CliError> {
+    let input = input_file(matches, config)?;
+    if !Path::new(&input).is_file() {
+        return Err(CliError::other(format!("cannot read input file {}", input)));
+    }
+    let mut queries = match schema_file(matches, config)? {
+        Some(schema) => load_queries(Path::new(&schema)).map_err(|e| CliError::other(e.to_string()))?,
+        None => Vec::new(),
+    };
+    queries.extend(load_queries(Path::new(&input)).map_err(|e| CliError::other(e.to_string()))?);
+    Ok(queries)
+}
+
+/// Flags that select the planning passes.
+const PASS_FLAGS: [&str; 5] = ["OVERLAP", "PERMUTATIONS", "SORTED", "NONPREFIX", "MEGAJOIN"];
+
+/// The config's planner settings, overridden by the command line. Any pass-selecting flag
+/// replaces the config's flags and pass list with exactly the flags given, and `--passes`
+/// replaces both.
+fn optimizations(matches: &ArgMatches, config: &Config) -> Result<Optimizations, CliError> {
+    let mut opts = config.optimizations();
+    if PASS_FLAGS.iter().any(|&flag| matches.is_present(flag)) {
+        opts.overlap = matches.is_present("OVERLAP");
+        opts.permutations = matches.is_present("PERMUTATIONS");
+        opts.sorted_names = matches.is_present("SORTED");
+        opts.nonprefix = matches.is_present("NONPREFIX");
+        opts.megajoin = matches.is_present("MEGAJOIN");
+        opts.passes = None;
+    }
+    if let Some(name) = matches.value_of("STRATEGY") {
+        opts.join_strategy = Some(String::from(name));
+    }
+    if let Some(passes) = matches.value_of("PASSES") {
+        opts.passes = Some(passes.parse().map_err(CliError::other)?);
+    }
+    Ok(opts)
+}
+
+fn load_start(matches: &ArgMatches) -> Result<Option<SavedGraph>, CliError> {
+    match matches.value_of("FROM") {
+        Some(path) => SavedGraph::load(Path::new(path))
+                          .map(Some)
+                          .map_err(|e| CliError::other(format!("loading graph {} failed: {}", path, e))),
+        None => Ok(None),
+    }
+}
+
+/// Plans the input without printing anything.
+fn plan_quietly(matches: &ArgMatches, config: &Config) -> Result<PlanReport, CliError> {
+    let queries = load_input(matches, config)?;
+    let opts = optimizations(matches, config)?;
+    let report = match load_start(matches)? {
+        Some(start) => {
+            let (graph, tables) = start.to_graph().map_err(|e| CliError::other(e.to_string()))?;
+            extend_queries(graph, tables, &queries, &opts)
+        }
+        None => plan_queries(&queries, &opts),
+    };
+    Ok(report)
+}
+
+/// Writes `graph` to `<name>.<format>` for every format configured in `output`. If the
+/// graphviz renderer is not installed, SVG falls back to the native renderer and any other
+/// format is an error.
+fn render_graph(graph: &Graph, output: &OutputConfig) -> Result<(), CliError> {
+    for format in output.formats() {
+        let path = output.rendered_name(format);
+        let rendered = match render_with_fallback(graph, &output.renderer, format, &output.dot_options()) {
+            Ok((rendered, fell_back)) => {
+                if fell_back {
+                    eprintln!("warning: {} not found, rendering {} with the native renderer", output.renderer, path);
+                }
+                rendered
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(CliError::other(format!("cannot run {} to render {}: {}; install graphviz, or use \
+                                                    --renderer native --format svg", output.renderer, path, e)));
+            }
+            Err(e) => return Err(CliError::other(format!("rendering {} failed: {}", path, e))),
+        };
+        File::create(&path).and_then(|mut f| f.write_all(&rendered))
+                           .map_err(|e| CliError::other(format!("cannot write {}: {}", path, e)))?;
+    }
+    Ok(())
+}
+
+fn plan(matches: &ArgMatches) -> Result<(), CliError> {
+    let mut config = load_config(matches)?;
+    if let Some(label) = matches.value_of("LABEL") {
+        config.report.label = String::from(label);
+    }
+    if let Some(name) = matches.value_of("OUTPUT") {
+        config.output.name = String::from(name);
+    }
+    if let Some(path) = matches.value_of("SAVE") {
+        config.output.save_graph = Some(String::from(path));
+    }
+    if let Some(format) = matches.value_of("FORMAT") {
+        config.output.format = String::from(format);
+    }
+    if let Some(renderer) = matches.value_of("RENDERER") {
+        config.output.renderer = String::from(renderer);
+    }
+    if matches.is_present("CLUSTERED") {
+        config.output.clustered = true;
+    }
+    let input = input_file(matches, &config)?;
+    if !Path::new(&input).is_file() {
+        return Err(CliError::other(format!("cannot read input file {}", input)));
+    }
+    let schema = schema_file(matches, &config)?;
+    let opts = optimizations(matches, &config)?;
+    let start = load_start(matches)?;
+
+    if matches.is_present("STEPS") {
+        config.output.steps = true;
+    }
+    let mut steps = if config.output.steps { Some(StepWriter::new(&config.output)) } else { None };
+
+    let output_file = Path::new(&config.output.name);
+    let planned = extend_queries_from_file(Path::new(&input), schema.as_ref().map(Path::new), &config.report.label,
+                                           opts, start.as_ref(), Some(output_file), steps.as_mut());
+    if let Some(steps) = steps {
+        if steps.fell_back() {
+            eprintln!("warning: {} not found, rendering svg steps with the native renderer", config.output.renderer);
+        }
+        let index = steps.index_name();
+        steps.finish().map_err(|e| CliError::other(format!("writing steps to {} failed: {}", index, e)))?;
+    }
+    // statements that failed are reported and skipped, so everything else is still written
+    let (report, problem) = match planned {
+        Ok(report) => (report, None),
+        Err(report) => {
+            if let Some(e) = report.errors.iter().find(|e| e.location().is_none()) {
+                return Err(CliError::other(e.to_string()));
+            }
+            let problem = format!("{} statements failed to parse, {} were skipped", report.failures.len(), report.errors.len());
+            (*report, Some(problem))
+        }
+    };
+    if let Some(ref path) = config.output.save_graph {
+        // keep the leaves of queries planned before the migration
+        let mut saved = report.saved_graph();
+        if let Some(start) = start {
+            // renumbered by what the migration dropped
+            let mut earlier: Vec<QueryResult> = start.queries.into_iter()
+                .map(|q| QueryResult { name: q.label().to_string(), sql: q.sql, leaf: q.leaf, new_nodes: Vec::new(), reused_nodes: Vec::new() })
+                .collect();
+            for removal in report.removals.iter() {
+                removal.apply(&mut earlier, 0);
+            }
+            earlier.extend(report.queries.iter().cloned());
+            saved = SavedGraph::new(&report.graph, &report.tables, &earlier);
+        }
+        saved.save(Path::new(path)).map_err(|e| CliError::other(format!("saving graph {} failed: {}", path, e)))?;
+    }
+    if config.output.clustered {
+        // the planner wrote the default layout
+        File::create(output_file).and_then(|mut f| f.write_all(graphviz_with(&report.graph, &config.output.dot_options()).as_bytes()))
+                                 .map_err(|e| CliError::other(format!("cannot write {}: {}", config.output.name, e)))?;
+    }
+    render_graph(&report.graph, &config.output)?;
+    match problem {
+        Some(problem) => Err(CliError::plan(problem)),
+        None => Ok(()),
+    }
+}
+
+fn compare(matches: &ArgMatches) -> Result<(), CliError> {
+    let config = load_config(matches)?;
+    let queries = load_input(matches, &config)?;
+    let format = match matches.value_of("FORMAT") {
+        Some(format) => format.parse().map_err(CliError::other)?,
+        None => config.report.format,
+    };
+    let results = compare_strategies(&queries, &config.optimizations());
+    print!("{}", format_comparison(&results, format));
+    Ok(())
+}
+
+fn render(matches: &ArgMatches) -> Result<(), CliError> {
+    let mut config = load_config(matches)?;
+    let path = matches.value_of("GRAPH").unwrap();
+    let saved = SavedGraph::load(Path::new(path))
+                           .map_err(|e| CliError::other(format!("loading graph {} failed: {}", path, e)))?;
+    config.output.name = match matches.value_of("OUTPUT") {
+        Some(name) => String::from(name),
+        None => String::from(path.trim_end_matches(".json")),
+    };
+    if let Some(format) = matches.value_of("FORMAT") {
+        config.output.format = String::from(format);
+    }
+    if let Some(renderer) = matches.value_of("RENDERER") {
+        config.output.renderer = String::from(renderer);
+    }
+    if matches.is_present("CLUSTERED") {
+        config.output.clustered = true;
+    }
+    let (graph, _) = saved.to_graph().map_err(|e| CliError::other(format!("loading graph {} failed: {}", path, e)))?;
+    render_graph(&graph, &config.output)
+}
+
+fn stats(matches: &ArgMatches) -> Result<(), CliError> {
+    let config = load_config(matches)?;
+    let queries = load_input(matches, &config)?;
+    let opts = optimizations(matches, &config)?;
+    let stats = workload_stats(&queries, &opts);
+    print!("{}", stats);
+    if stats.failures > 0 || stats.skipped > 0 {
+        return Err(CliError::plan(format!("{} statements failed to parse, {} were skipped", stats.failures, stats.skipped)));
+    }
+    Ok(())
+}
+
+fn explain(matches: &ArgMatches) -> Result<(), CliError> {
+    let config = load_config(matches)?;
+    let report = plan_quietly(matches, &config)?;
+    let name = matches.value_of("QUERY").unwrap();
+    match report.explain(name) {
+        Some(explanation) => {
+            print!("{}", explanation);
+            Ok(())
+        }
+        None => Err(CliError::plan(format!("no query named '{}' was planned", name))),
+    }
 }
@@ -0,0 +1,292 @@
+extern crate permutohedron;
+
+use graph::{Graph, NodeIndex, TestNodeData};
+use join::{all_acceptable, find_join, get_all_ancestors, make_combined_joins, make_inner_join, some_needed};
+use Optimizations;
+
+use self::permutohedron::Heap;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+
+/// One step of planning the joins of a SELECT. Passes run in order over a shared `JoinPlan`;
+/// whatever relations are left after the last pass are joined with fresh inner joins.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Pass {
+    /// Sorts the relations by name, so queries that list the same tables in a different order
+    /// end up with the same join order.
+    CanonicalizeNames,
+    /// Reuses existing two-way joins over any subset of the relations, wherever those relations
+    /// sit in the join order, and joins them together.
+    MatchSubsets,
+    /// Reorders the remaining relations to maximize the number of existing joins `MatchReuse`
+    /// will find, trying every permutation.
+    ChooseJoinOrder,
+    /// Walks the remaining relations in order and reuses existing joins for as long as the next
+    /// relation is already joined with what has been built so far.
+    MatchReuse,
+    /// Outer-joins every relation into the one chain shared by all queries. This builds the
+    /// result on its own, so passes after it are skipped.
+    ExpandMegajoin,
+}
+
+impl Pass {
+    /// Every pass, in the order `Pipeline::enable` places them.
+    pub fn all() -> Vec<Pass> {
+        vec![Pass::CanonicalizeNames,
+             Pass::MatchSubsets,
+             Pass::ChooseJoinOrder,
+             Pass::MatchReuse,
+             Pass::ExpandMegajoin]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Pass::CanonicalizeNames => "canonicalize-names",
+            Pass::MatchSubsets => "match-subsets",
+            Pass::ChooseJoinOrder => "choose-join-order",
+            Pass::MatchReuse => "match-reuse",
+            Pass::ExpandMegajoin => "expand-megajoin",
+        }
+    }
+}
+
+impl FromStr for Pass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Pass::all()
+            .into_iter()
+            .find(|p| p.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Pass::all().iter().map(|p| p.name()).collect();
+                format!("unknown pass '{}' (expected one of {})", s, names.join(", "))
+            })
+    }
+}
+
+impl fmt::Display for Pass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+
+/// An ordered list of passes.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Pipeline {
+    pub passes: Vec<Pass>,
+}
+
+impl Pipeline {
+    pub fn new(passes: Vec<Pass>) -> Self {
+        Pipeline { passes }
+    }
+
+    /// The pipeline that plans exactly like the given flags. Only one join mode is honored
+    /// (megajoin, then permutations, then nonprefix), and permutation search only happens with
+    /// `overlap`, since without it no order reuses anything.
+    pub fn from_flags(opts: &Optimizations) -> Self {
+        let mut pipeline = Pipeline::default();
+        if opts.sorted_names {
+            pipeline.enable(Pass::CanonicalizeNames);
+        }
+        if opts.megajoin {
+            pipeline.enable(Pass::ExpandMegajoin);
+            return pipeline;
+        }
+        if opts.permutations && opts.overlap {
+            pipeline.enable(Pass::ChooseJoinOrder);
+        } else if !opts.permutations && opts.nonprefix {
+            pipeline.enable(Pass::MatchSubsets);
+        }
+        if opts.overlap {
+            pipeline.enable(Pass::MatchReuse);
+        }
+        pipeline
+    }
+
+    pub fn contains(&self, pass: Pass) -> bool {
+        self.passes.contains(&pass)
+    }
+
+    /// Adds `pass` at its usual position (see `Pass::all`), unless it is already in the pipeline.
+    pub fn enable(&mut self, pass: Pass) {
+        if self.contains(pass) {
+            return;
+        }
+        let at = self.passes.iter().position(|&p| p > pass).unwrap_or(self.passes.len());
+        self.passes.insert(at, pass);
+    }
+
+    pub fn disable(&mut self, pass: Pass) {
+        self.passes.retain(|&p| p != pass);
+    }
+
+    /// Pass names in order, e.g. `match-subsets>match-reuse`, or `none` if the pipeline is empty.
+    pub fn label(&self) -> String {
+        if self.passes.is_empty() {
+            return String::from("none");
+        }
+        let names: Vec<&str> = self.passes.iter().map(|p| p.name()).collect();
+        names.join(">")
+    }
+
+    /// Runs every pass over `relations` and returns the node producing their join.
+    pub fn run(&self, relations: &[String], catalog: &HashMap<String, NodeIndex>, graph: &mut Graph) -> NodeIndex {
+        let mut plan = JoinPlan { relations: relations.to_vec(), covered: Vec::new(), start: None };
+        for &pass in self.passes.iter() {
+            if let Some(result) = plan.apply(pass, catalog, graph) {
+                return result;
+            }
+        }
+        plan.finish(catalog, graph)
+    }
+}
+
+impl FromStr for Pipeline {
+    type Err = String;
+
+    /// Parses a comma-separated list of pass names.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let passes = s.split(',')
+                      .map(|p| p.trim())
+                      .filter(|p| !p.is_empty())
+                      .map(|p| p.parse())
+                      .collect::<Result<Vec<Pass>, String>>()?;
+        Ok(Pipeline::new(passes))
+    }
+}
+
+
+/// The state passes work on: the relations still to be joined, in join order, and the node
+/// joining the ones already taken care of.
+struct JoinPlan {
+    relations: Vec<String>,
+    covered: Vec<String>,
+    start: Option<NodeIndex>,
+}
+
+impl JoinPlan {
+    /// Returns the finished join if the pass built it.
+    fn apply(&mut self, pass: Pass, catalog: &HashMap<String, NodeIndex>, graph: &mut Graph) -> Option<NodeIndex> {
+        match pass {
+            Pass::CanonicalizeNames => {
+                self.relations.sort();
+                self.relations.dedup();
+            }
+            Pass::MatchSubsets => self.match_subsets(graph),
+            Pass::ChooseJoinOrder => self.choose_join_order(catalog, graph),
+            Pass::MatchReuse => self.match_reuse(catalog, graph),
+            Pass::ExpandMegajoin => {
+                let mut names = self.covered.clone();
+                names.append(&mut self.relations.clone());
+                return Some(make_combined_joins(names, catalog, graph));
+            }
+        }
+        None
+    }
+
+    fn take(&mut self, n: usize) {
+        let mut taken: Vec<String> = self.relations.drain(..n).collect();
+        self.covered.append(&mut taken);
+    }
+
+    fn match_subsets(&mut self, graph: &mut Graph) {
+        let mut covered_tables: Vec<String> = Vec::new();
+        let mut existing_joins: Vec<NodeIndex> = Vec::new();
+        for node in graph.iter() {
+            if node.data == TestNodeData::InnerJoin {
+                let mut tables = get_all_ancestors(node.index, graph);
+                if all_acceptable(&tables, &self.relations) && some_needed(&tables, &self.relations, &covered_tables) {
+                    existing_joins.push(node.index);
+                    covered_tables.append(&mut tables);
+                }
+            }
+        }
+        for existing in existing_joins {
+            self.start = Some(match self.start {
+                Some(prev) => make_inner_join(prev, existing, graph),
+                None => existing,
+            });
+        }
+        let (mut covered, remaining) = self.relations.drain(..).partition(|r| covered_tables.contains(r));
+        self.covered.append(&mut covered);
+        self.relations = remaining;
+    }
+
+    /// How many relations at the front of `order` `match_reuse` would find existing joins for.
+    fn reusable_prefix(&self, order: &[String], catalog: &HashMap<String, NodeIndex>, graph: &Graph) -> usize {
+        let (mut prev, rest) = match self.start {
+            Some(start) => (start, order),
+            None => (catalog[&order[0]], &order[1..]),
+        };
+        let mut count = 0;
+        for name in rest {
+            match find_join(prev, catalog[name], graph) {
+                Some(existing) => prev = existing,
+                None => break,
+            }
+            count += 1;
+        }
+        count
+    }
+
+    fn choose_join_order(&mut self, catalog: &HashMap<String, NodeIndex>, graph: &Graph) {
+        if self.relations.is_empty() {
+            return;
+        }
+        let mut names = self.relations.clone();
+        let heap = Heap::new(&mut names);
+
+        let mut best_order = self.relations.clone();
+        let mut best_overlap = 0;
+        for name_order in heap {
+            let overlap = self.reusable_prefix(&name_order, catalog, graph);
+            if overlap > best_overlap {
+                best_overlap = overlap;
+                best_order = name_order;
+            }
+        }
+        self.relations = best_order;
+    }
+
+    fn match_reuse(&mut self, catalog: &HashMap<String, NodeIndex>, graph: &Graph) {
+        if self.relations.is_empty() {
+            return;
+        }
+        let reused = self.reusable_prefix(&self.relations, catalog, graph);
+        let mut prev = match self.start {
+            Some(start) => start,
+            None => {
+                let first = catalog[&self.relations[0]];
+                self.take(1);
+                first
+            }
+        };
+        for name in self.relations[..reused].iter() {
+            prev = find_join(prev, catalog[name], graph).unwrap();
+        }
+        self.take(reused);
+        self.start = Some(prev);
+    }
+
+    /// Joins whatever relations no pass took care of onto the result with fresh inner joins.
+    fn finish(mut self, catalog: &HashMap<String, NodeIndex>, graph: &mut Graph) -> NodeIndex {
+        let mut prev = match self.start {
+            Some(start) => start,
+            None => {
+                assert!(!self.relations.is_empty(), "make_select rejects queries without relations");
+                let first = catalog[&self.relations[0]];
+                self.take(1);
+                first
+            }
+        };
+        for name in self.relations.iter() {
+            prev = make_inner_join(prev, catalog[name], graph);
+        }
+        prev
+    }
+}
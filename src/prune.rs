@@ -0,0 +1,104 @@
+use graph::{BaseColumn, Column, Graph, NodeIndex, TestNodeData};
+use resolve::{Resolution, ResolvedColumn};
+
+use std::collections::HashMap;
+
+
+/// Every column a query reads: those its select list, predicates, grouping and ordering refer
+/// to, including join keys and the outer columns its subqueries use, plus every column of the
+/// relations it selects `*` from.
+pub fn needed_columns(resolution: &Resolution, all: bool, starred: &[String]) -> Vec<ResolvedColumn> {
+    let mut needed: Vec<ResolvedColumn> = Vec::new();
+    let mut pending = vec![resolution];
+    while let Some(r) = pending.pop() {
+        needed.extend(r.columns().into_iter().cloned());
+        pending.extend(r.subqueries.iter());
+    }
+    for relation in resolution.relations.iter() {
+        if all || starred.iter().any(|s| *s == relation.name || *s == relation.table) {
+            needed.extend(relation.columns.iter().map(|c| ResolvedColumn {
+                relation: relation.name.clone(),
+                table: relation.table.clone(),
+                column: c.name.clone(),
+            }));
+        }
+    }
+    needed.sort();
+    needed.dedup();
+    needed
+}
+
+/// Whether `column` carries the values of `needed`: it is that column, or an equi-joined key
+/// it was merged into.
+fn provides(column: &Column, needed: &ResolvedColumn) -> bool {
+    column.name == needed.column && (column.table.as_ref() == Some(&needed.table) ||
+                                     column.lineage.contains(&BaseColumn { table: needed.table.clone(), name: needed.column.clone() }))
+}
+
+/// The projection of base table `base` that joins read it through, if there is one.
+pub fn narrow_projection(base: NodeIndex, graph: &Graph) -> Option<NodeIndex> {
+    graph[base].children.iter().cloned().find(|&c| graph[c].data == TestNodeData::Project && graph[c].name == graph[base].name)
+}
+
+/// `tables` with every base table in `relations` replaced by its narrow projection, which is
+/// created if needed and widened to the columns of `needed` it lacks.
+pub fn narrow_inputs(relations: &[String], needed: &[ResolvedColumn], tables: &HashMap<String, NodeIndex>,
+                     graph: &mut Graph) -> HashMap<String, NodeIndex> {
+    let mut inputs = tables.clone();
+    for name in relations.iter() {
+        let base = tables[name];
+        if !graph[base].is_base() {
+            continue;
+        }
+        let projection = match narrow_projection(base, graph) {
+            Some(projection) => projection,
+            None => {
+                let maxrows = graph[base].maxrows;
+                graph.add_node(name, TestNodeData::Project, Vec::new(), vec![base], maxrows)
+            }
+        };
+        let columns = widened(&graph[projection].columns, &graph[base].columns, needed);
+        graph[projection].columns = columns;
+        inputs.insert(name.clone(), projection);
+    }
+    inputs
+}
+
+/// `current` plus the columns of `available` that provide something in `needed` that
+/// `current` does not, in the order of `available`.
+fn widened(current: &[Column], available: &[Column], needed: &[ResolvedColumn]) -> Vec<Column> {
+    let missing: Vec<&ResolvedColumn> = needed.iter().filter(|n| !current.iter().any(|c| provides(c, n))).collect();
+    available.iter()
+             .filter_map(|a| match current.iter().find(|c| c.same_source(a)) {
+                 Some(c) => Some(c.clone()),
+                 None if missing.iter().any(|n| provides(a, n)) => Some(a.clone()),
+                 None => None,
+             })
+             .collect()
+}
+
+/// Narrows the joins below `join_result` to the columns queries read through them. Joins added
+/// since `first_new` carry exactly the `needed` columns they have; joins reused from earlier
+/// queries keep their columns and gain the ones this query needs, so a shared join carries the
+/// union of what its consumers read.
+pub fn prune_joins(join_result: NodeIndex, needed: &[ResolvedColumn], first_new: NodeIndex, graph: &mut Graph) {
+    let mut joins: Vec<NodeIndex> = graph.ancestor_closure(join_result)
+                                         .into_iter()
+                                         .filter(|&n| graph[n].is_join())
+                                         .collect();
+    // inputs before the joins that read them
+    joins.sort();
+    for join in joins {
+        let columns = if join >= first_new {
+            graph[join].columns.iter().filter(|c| needed.iter().any(|n| provides(c, n))).cloned().collect()
+        } else {
+            let outer = graph[join].data == TestNodeData::OuterJoin;
+            let mut available = Vec::new();
+            for (side, &input) in graph[join].ancestors.iter().enumerate() {
+                available.extend(graph.output_columns(input).into_iter().map(|c| Column { nullable: c.nullable || (outer && side > 0), ..c }));
+            }
+            widened(&graph[join].columns, &available, needed)
+        };
+        graph[join].columns = columns;
+    }
+}
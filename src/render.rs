@@ -0,0 +1,78 @@
+use graph::Graph;
+use export::get_exporter;
+use graphviz::{graphviz_with, DotOptions};
+use svg::svg_with;
+
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+
+
+/// Renderer name that selects the built-in layout instead of a graphviz program.
+pub const NATIVE_RENDERER: &str = "native";
+
+/// Renders `graph` in `format`. Formats with an `Exporter`, like `dot` or `graphml`, are written
+/// directly; anything else is produced by `renderer`, either `NATIVE_RENDERER` (SVG only) or a graphviz program run as
+/// `<renderer> -T<format>`. If that program is not installed the error is `NotFound`; if it
+/// fails, even before reading the whole graph, the error has its exit status and stderr.
+pub fn render(graph: &Graph, renderer: &str, format: &str, options: &DotOptions) -> io::Result<Vec<u8>> {
+    if let Some(exporter) = get_exporter(format, options) {
+        return Ok(exporter.export(graph).into_bytes());
+    }
+    if renderer == NATIVE_RENDERER {
+        if format != "svg" {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      format!("the native renderer only writes svg, not {}", format)));
+        }
+        return Ok(svg_with(graph, options).into_bytes());
+    }
+
+    let mut child = Command::new(renderer)
+                            .arg(format!("-T{}", format))
+                            .stdin(Stdio::piped())
+                            .stdout(Stdio::piped())
+                            .stderr(Stdio::piped())
+                            .spawn()?;
+    // written from another thread, so a renderer that fills its output before reading all its
+    // input, or exits without reading it, cannot block us or hide its own error
+    let mut stdin = child.stdin.take().unwrap();
+    let dot = graphviz_with(graph, options);
+    let writer = thread::spawn(move || stdin.write_all(dot.as_bytes()));
+    let output = child.wait_with_output()?;
+    let written = writer.join().unwrap();
+    if !output.status.success() {
+        return Err(io::Error::other(format!("{} -T{} failed with {}: {}", renderer, format, output.status,
+                                            String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    written?;
+    Ok(output.stdout)
+}
+
+/// Like `render`, but if `renderer` is not installed SVG is drawn by the native renderer instead.
+/// The flag says whether that happened.
+pub fn render_with_fallback(graph: &Graph, renderer: &str, format: &str, options: &DotOptions)
+                            -> io::Result<(Vec<u8>, bool)> {
+    match render(graph, renderer, format, options) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound && format == "svg" => {
+            render(graph, NATIVE_RENDERER, format, options).map(|rendered| (rendered, true))
+        }
+        rendered => rendered.map(|rendered| (rendered, false)),
+    }
+}
+
+
+#[test]
+fn test_renderer_failure_is_reported() {
+    use graph::plan_queries;
+    use {load_queries, Optimizations};
+    use std::path::Path;
+
+    let mut queries = load_queries(Path::new("tests/tpc-w-queries.txt")).unwrap();
+    queries.extend((0..500).map(|i| format!("CREATE VIEW customer_{} AS SELECT c_uname FROM customer WHERE c_id = ?;", i)));
+    let report = plan_queries(&queries, &Optimizations::new());
+    // `false` exits at once without reading the graph, which is larger than a pipe buffer
+    assert!(graphviz_with(&report.graph, &DotOptions::default()).len() > 1 << 16);
+    let error = render(&report.graph, "false", "png", &DotOptions::default()).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::Other);
+    assert!(error.to_string().starts_with("false -Tpng failed with exit status: 1"), "{}", error);
+}
@@ -0,0 +1,413 @@
+use graph::{Graph, NodeIndex, TestNode, TestNodeData, remove_query};
+use saved::SavedGraph;
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+
+/// What planning one SELECT or view did to the graph.
+#[derive(Clone, Debug)]
+pub struct QueryResult {
+    /// The view or query name. SELECTs without one are named `query_<n>` after their position
+    /// in the batch.
+    pub name: String,
+    pub sql: String,
+    /// Index of the node that produces the query's output.
+    pub leaf: NodeIndex,
+    /// Indices of the nodes this query added to the graph.
+    pub new_nodes: Vec<NodeIndex>,
+    /// Indices of non-base nodes that already existed and that this query now depends on.
+    pub reused_nodes: Vec<NodeIndex>,
+}
+
+impl QueryResult {
+    /// Fraction of the non-base nodes in this query's plan that it reused rather than added.
+    pub fn reuse_fraction(&self) -> f64 {
+        let total = self.new_nodes.len() + self.reused_nodes.len();
+        if total == 0 {
+            return 0.0;
+        }
+        self.reused_nodes.len() as f64 / total as f64
+    }
+
+    /// The name the query's nodes record it as a user by, which is `name`.
+    pub fn label(&self) -> &str {
+        &self.name
+    }
+}
+
+/// What removing a query, view or table freed.
+#[derive(Clone, Debug)]
+pub struct Removal {
+    pub name: String,
+    /// The freed nodes, as `n<index> <name>` with their indices from before the removal.
+    pub freed_nodes: Vec<String>,
+    pub freed_rows: usize,
+    pub freed_bytes: usize,
+    /// New index of each node that existed before the removal, or `None` if it was freed.
+    pub remap: Vec<Option<NodeIndex>>,
+}
+
+impl Removal {
+    /// Updates query results planned before this removal to the renumbered graph and drops the
+    /// result for the removed query itself. Returns `first_new` adjusted the same way.
+    pub fn apply(&self, queries: &mut Vec<QueryResult>, first_new: usize) -> usize {
+        queries.retain(|q| q.name != self.name);
+        for q in queries.iter_mut() {
+            if let Some(leaf) = self.remap[q.leaf] {
+                q.leaf = leaf;
+            }
+            q.new_nodes = q.new_nodes.iter().filter_map(|&i| self.remap[i]).collect();
+            q.reused_nodes = q.reused_nodes.iter().filter_map(|&i| self.remap[i]).collect();
+        }
+        self.remap[..first_new].iter().filter(|i| i.is_some()).count()
+    }
+}
+
+/// A statement nom-sql rejected.
+#[derive(Clone, Debug)]
+pub struct ParseFailure {
+    /// The name the statement was given in the workload, if it was a named query or view.
+    pub name: Option<String>,
+    pub sql: String,
+    pub error: String,
+    /// Index of the statement in the planned batch.
+    pub position: usize,
+    /// Line of the workload file the statement starts on, when it was read from one.
+    pub line: Option<usize>,
+}
+
+impl ParseFailure {
+    /// The name if there is one, otherwise the SQL text. Unlike planned SELECTs, statements
+    /// that fail to parse are not named `query_<n>`, since they may not be SELECTs at all.
+    pub fn label(&self) -> &str {
+        match self.name {
+            Some(ref name) => name,
+            None => self.sql.trim(),
+        }
+    }
+}
+
+/// Where a statement sits in the workload.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Location {
+    /// The query or view name, if the statement has one.
+    pub name: Option<String>,
+    /// Index of the statement in the planned batch.
+    pub position: usize,
+    /// Line of the workload file the statement starts on, when it was read from one.
+    pub line: Option<usize>,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.name {
+            Some(ref name) => write!(f, "'{}'", name)?,
+            None => write!(f, "statement {}", self.position + 1)?,
+        }
+        match self.line {
+            Some(line) => write!(f, " (line {})", line),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Why a workload, or a statement in it that parsed, could not be planned. The planner reports
+/// these and goes on with the next statement, leaving the graph as it was.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PlanError {
+    /// The workload file could not be read.
+    Io { path: String, error: String },
+    /// The graph could not be written to the output file.
+    Write { path: String, error: String },
+    /// A saved graph refers to nodes it does not have, or lists its nodes out of order.
+    InvalidGraph { reason: String },
+    /// The query reads a table or view that does not exist.
+    UnknownTable { at: Location, table: String },
+    /// A UNION, EXCEPT or INTERSECT, which the planner has no operators for.
+    CompoundSelect { at: Location },
+    /// A JOIN whose right-hand side is a subquery or a list of tables rather than one table.
+    NestedJoin { at: Location },
+    /// A SELECT without a FROM clause.
+    EmptyFrom { at: Location },
+    /// A query that reads the same table more than once, e.g. under two aliases.
+    SelfJoin { at: Location, table: String },
+    /// The graph has a join that does not have exactly two ancestors, which only a hand-edited
+    /// saved graph can contain.
+    NonBinaryJoin { at: Location, node: NodeIndex },
+    /// No join strategy is registered under the name the planner was configured with.
+    UnknownStrategy { at: Location, strategy: String },
+    /// A column that none of the relations in scope has, with the most similar ones that exist.
+    UnknownColumn { at: Location, column: String, suggestions: Vec<String> },
+    /// An unqualified column that more than one relation in scope has.
+    AmbiguousColumn { at: Location, column: String, candidates: Vec<String> },
+}
+
+impl PlanError {
+    /// The statement the error is about, or `None` for errors about the whole workload.
+    pub fn location(&self) -> Option<&Location> {
+        match *self {
+            PlanError::Io { .. } | PlanError::Write { .. } | PlanError::InvalidGraph { .. } => None,
+            PlanError::UnknownTable { ref at, .. } |
+            PlanError::CompoundSelect { ref at } |
+            PlanError::NestedJoin { ref at } |
+            PlanError::EmptyFrom { ref at } |
+            PlanError::SelfJoin { ref at, .. } |
+            PlanError::NonBinaryJoin { ref at, .. } |
+            PlanError::UnknownStrategy { ref at, .. } |
+            PlanError::UnknownColumn { ref at, .. } |
+            PlanError::AmbiguousColumn { ref at, .. } => Some(at),
+        }
+    }
+
+    pub fn location_mut(&mut self) -> Option<&mut Location> {
+        match *self {
+            PlanError::Io { .. } | PlanError::Write { .. } | PlanError::InvalidGraph { .. } => None,
+            PlanError::UnknownTable { ref mut at, .. } |
+            PlanError::CompoundSelect { ref mut at } |
+            PlanError::NestedJoin { ref mut at } |
+            PlanError::EmptyFrom { ref mut at } |
+            PlanError::SelfJoin { ref mut at, .. } |
+            PlanError::NonBinaryJoin { ref mut at, .. } |
+            PlanError::UnknownStrategy { ref mut at, .. } |
+            PlanError::UnknownColumn { ref mut at, .. } |
+            PlanError::AmbiguousColumn { ref mut at, .. } => Some(at),
+        }
+    }
+}
+
+impl fmt::Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PlanError::Io { ref path, ref error } => write!(f, "cannot read {}: {}", path, error),
+            PlanError::Write { ref path, ref error } => write!(f, "cannot write {}: {}", path, error),
+            PlanError::InvalidGraph { ref reason } => write!(f, "invalid saved graph: {}", reason),
+            PlanError::UnknownTable { ref at, ref table } => write!(f, "{}: unknown table '{}'", at, table),
+            PlanError::CompoundSelect { ref at } => write!(f, "{}: compound selects are not supported", at),
+            PlanError::NestedJoin { ref at } => write!(f, "{}: only tables can be joined, not subqueries or nested joins", at),
+            PlanError::EmptyFrom { ref at } => write!(f, "{}: the query reads no tables", at),
+            PlanError::SelfJoin { ref at, ref table } => write!(f, "{}: self-joins are not supported, '{}' is read more than once", at, table),
+            PlanError::NonBinaryJoin { ref at, node } => {
+                write!(f, "{}: join n{} does not have exactly two ancestors", at, node)
+            }
+            PlanError::UnknownStrategy { ref at, ref strategy } => {
+                write!(f, "{}: no join strategy registered as '{}'", at, strategy)
+            }
+            PlanError::UnknownColumn { ref at, ref column, ref suggestions } => {
+                write!(f, "{}: unknown column '{}'", at, column)?;
+                match suggestions.split_last() {
+                    None => Ok(()),
+                    Some((last, [])) => write!(f, "; did you mean {}?", last),
+                    Some((last, rest)) => write!(f, "; did you mean {} or {}?", rest.join(", "), last),
+                }
+            }
+            PlanError::AmbiguousColumn { ref at, ref column, ref candidates } => {
+                write!(f, "{}: column '{}' is ambiguous, it could be {}", at, column, candidates.join(" or "))
+            }
+        }
+    }
+}
+
+impl error::Error for PlanError {}
+
+/// Everything `parse_queries` learned about a workload: per-query results, parse failures,
+/// statements that could not be planned and the final graph.
+#[derive(Debug)]
+pub struct PlanReport {
+    pub queries: Vec<QueryResult>,
+    pub failures: Vec<ParseFailure>,
+    /// Statements that parsed but were skipped because the planner cannot handle them.
+    pub errors: Vec<PlanError>,
+    pub removals: Vec<Removal>,
+    /// Number of statements that parsed, including ones that add no nodes.
+    pub parsed_ok: usize,
+    /// Index of the first node added by this batch; everything before it was already in the
+    /// graph the batch was planned on top of.
+    pub first_new: usize,
+    pub graph: Graph,
+    /// Base tables and views by name, as later statements would see them.
+    pub tables: HashMap<String, NodeIndex>,
+}
+
+impl PlanReport {
+    pub fn num_nodes(&self) -> usize {
+        self.graph.len()
+    }
+
+    pub fn num_joins(&self) -> usize {
+        self.graph.iter().filter(|node| node.is_join()).count()
+    }
+
+    pub fn num_outer_joins(&self) -> usize {
+        self.graph.iter().filter(|node| node.data == TestNodeData::OuterJoin).count()
+    }
+
+    /// Estimated rows held across all non-base nodes.
+    pub fn total_rows(&self) -> usize {
+        self.graph.total_rows()
+    }
+
+    /// Estimated bytes held across all non-base nodes.
+    pub fn total_bytes(&self) -> usize {
+        self.graph.total_bytes()
+    }
+
+    /// Total number of reuse events, summed over all queries.
+    pub fn total_reused(&self) -> usize {
+        self.queries.iter().map(|q| q.reused_nodes.len()).sum()
+    }
+
+    /// Number of queries using each non-base node, in node order.
+    pub fn sharing_factors(&self) -> Vec<(NodeIndex, usize)> {
+        self.graph.iter()
+                  .filter(|node| !node.is_base())
+                  .map(|node| (node.index, node.users.len()))
+                  .collect()
+    }
+
+    /// Fraction of non-base nodes used by more than one query.
+    pub fn shared_fraction(&self) -> f64 {
+        let factors = self.sharing_factors();
+        if factors.is_empty() {
+            return 0.0;
+        }
+        factors.iter().filter(|&&(_, users)| users > 1).count() as f64 / factors.len() as f64
+    }
+
+    /// Mean number of queries using a non-base node.
+    pub fn mean_sharing(&self) -> f64 {
+        let factors = self.sharing_factors();
+        if factors.is_empty() {
+            return 0.0;
+        }
+        factors.iter().map(|&(_, users)| users).sum::<usize>() as f64 / factors.len() as f64
+    }
+
+    pub fn query(&self, name: &str) -> Option<&QueryResult> {
+        self.queries.iter().find(|q| q.name == name)
+    }
+
+    /// Removes a query or view planned earlier, in this batch or before it, and frees the nodes
+    /// no remaining query uses. SELECTs without a name are removed as `query_<n>`.
+    pub fn remove_query(&mut self, name: &str) -> Option<&Removal> {
+        let removal = remove_query(name, &mut self.graph, &mut self.tables)?;
+        self.first_new = removal.apply(&mut self.queries, self.first_new);
+        self.removals.push(removal);
+        self.removals.last()
+    }
+
+    /// The nodes this batch added to the graph.
+    pub fn added_nodes(&self) -> &[TestNode] {
+        &self.graph.nodes()[self.first_new..]
+    }
+
+    fn describe_nodes(&self, indices: &[NodeIndex]) -> String {
+        let names: Vec<String> = indices.iter()
+                                        .map(|&i| format!("n{} {}", i, self.graph[i].name))
+                                        .collect();
+        if names.is_empty() {
+            String::from("nothing")
+        } else {
+            names.join(", ")
+        }
+    }
+
+    /// Describes this batch as a migration onto the graph it started from: every node it adds,
+    /// then which nodes each query reused and added.
+    pub fn migration_plan(&self) -> String {
+        let mut s = format!("MIGRATION: {} existing nodes, {} added\n", self.first_new, self.added_nodes().len());
+        for node in self.added_nodes() {
+            s.push_str(&format!("  + n{} {} {:?}\n", node.index, node.name, node.data));
+        }
+        for q in self.queries.iter() {
+            s.push_str(&format!("QUERY {}\n", q.label()));
+            s.push_str(&format!("  reuses {}\n", self.describe_nodes(&q.reused_nodes)));
+            s.push_str(&format!("  adds   {}\n", self.describe_nodes(&q.new_nodes)));
+        }
+        s
+    }
+
+    /// Finds a query by its name, `query_<n>` for SELECTs the workload does not name.
+    pub fn query_by_label(&self, label: &str) -> Option<&QueryResult> {
+        self.queries.iter().find(|q| q.label() == label.trim())
+    }
+
+    fn explain_node(&self, q: &QueryResult, node: NodeIndex, indent: usize, s: &mut String) {
+        let n = &self.graph[node];
+        let decision = if n.is_base() {
+            String::from("base")
+        } else if q.new_nodes.contains(&node) {
+            String::from("new")
+        } else {
+            let others: Vec<&str> = n.users.iter()
+                                     .map(|u| u.as_str())
+                                     .filter(|&u| u != q.label())
+                                     .collect();
+            format!("reused, shared with {}", others.join(", "))
+        };
+        let kind = match n.data {
+            TestNodeData::Base{..} => String::from("Base"),
+            ref data => format!("{:?}", data),
+        };
+        let spaces = (0..indent*3).map(|_| " ").collect::<String>();
+        s.push_str(&format!("{}-- n{} {}:{} [{} rows, {}]\n", spaces, node, n.name, kind, n.maxrows, decision));
+        for &a in n.ancestors.iter() {
+            self.explain_node(q, a, indent+1, s);
+        }
+    }
+
+    /// Shows how the query `label` was planned: its node tree, and for every node whether the
+    /// query added it or reused it from earlier queries. `None` if no such query was planned.
+    pub fn explain(&self, label: &str) -> Option<String> {
+        let q = self.query_by_label(label)?;
+        let mut s = format!("QUERY {}\n", q.label());
+        s.push_str(&format!("  sql: {}\n", q.sql.trim()));
+        s.push_str(&format!("  {} new nodes, {} reused\n", q.new_nodes.len(), q.reused_nodes.len()));
+        let key = &self.graph[q.leaf].reader_key;
+        if !key.is_empty() {
+            let columns: Vec<String> = key.iter().map(|c| c.qualified_name()).collect();
+            s.push_str(&format!("  reader key: {}\n", columns.join(", ")));
+        }
+        self.explain_node(q, q.leaf, 1, &mut s);
+        Some(s)
+    }
+
+    pub fn saved_graph(&self) -> SavedGraph {
+        SavedGraph::new(&self.graph, &self.tables, &self.queries)
+    }
+
+    /// Writes the final graph, table map and query leaves to `path` as JSON.
+    pub fn save_graph(&self, path: &Path) -> io::Result<()> {
+        self.saved_graph().save(path)
+    }
+}
+
+impl fmt::Display for PlanReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for q in self.queries.iter() {
+            writeln!(f, "planned '{}': {} new nodes, {} reused ({:.0}% reused)",
+                     q.label(), q.new_nodes.len(), q.reused_nodes.len(), q.reuse_fraction() * 100.0)?;
+        }
+        for removal in self.removals.iter() {
+            writeln!(f, "removed '{}': freed {} nodes, {} rows, {} bytes",
+                     removal.name, removal.freed_nodes.len(), removal.freed_rows, removal.freed_bytes)?;
+        }
+        for failure in self.failures.iter() {
+            match failure.line {
+                Some(line) => writeln!(f, "failed to parse '{}' (line {}): {}", failure.label(), line, failure.error)?,
+                None => writeln!(f, "failed to parse '{}': {}", failure.label(), failure.error)?,
+            }
+        }
+        for error in self.errors.iter() {
+            writeln!(f, "skipped {}", error)?;
+        }
+        for (node, users) in self.sharing_factors() {
+            writeln!(f, "node n{} {}: used by {} queries", node, self.graph[node].name, users)?;
+        }
+        writeln!(f, "NUM_NODES: {}\nNUM_JOINS: {}", self.num_nodes(), self.num_joins())?;
+        writeln!(f, "SHARED_NODES: {:.1}%\nMEAN_SHARING: {:.2}", self.shared_fraction() * 100.0, self.mean_sharing())
+    }
+}
@@ -0,0 +1,395 @@
+extern crate nom_sql;
+
+use nom_sql::{ArithmeticBase, ConditionBase, ConditionExpression, FieldDefinitionExpression, FieldValueExpression,
+    FunctionExpression, JoinConstraint, JoinRightSide, SelectStatement};
+use graph::{Column, Graph, NodeIndex};
+use report::{Location, PlanError};
+use strategy::JoinPredicate;
+
+use std::collections::HashMap;
+use std::fmt;
+
+
+/// Most suggestions an unknown column is reported with.
+const MAX_SUGGESTIONS: usize = 3;
+
+/// A column reference bound to the relation instance it reads.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ResolvedColumn {
+    /// Name the query refers to the relation by: its alias, or its name if it has none.
+    pub relation: String,
+    /// The table or view behind `relation`.
+    pub table: String,
+    pub column: String,
+}
+
+impl ResolvedColumn {
+    /// The reference as a nom-sql column qualified with its relation instance.
+    pub fn to_column(&self) -> nom_sql::Column {
+        nom_sql::Column { name: self.column.clone(), alias: None, table: Some(self.relation.clone()), function: None }
+    }
+}
+
+impl fmt::Display for ResolvedColumn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.relation, self.column)
+    }
+}
+
+/// One relation a query reads, under the name the query refers to it by.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RelationInstance {
+    pub name: String,
+    pub table: String,
+    pub columns: Vec<Column>,
+}
+
+impl RelationInstance {
+    fn column(&self, name: &str) -> Option<ResolvedColumn> {
+        self.columns.iter().find(|c| c.name == name).map(|c| ResolvedColumn {
+            relation: self.name.clone(),
+            table: self.table.clone(),
+            column: c.name.clone(),
+        })
+    }
+
+    fn column_names(&self) -> impl Iterator<Item = String> + '_ {
+        self.columns.iter().map(move |c| format!("{}.{}", self.name, c.name))
+    }
+}
+
+/// Where the column references of one SELECT point.
+#[derive(Clone, Debug, Default)]
+pub struct Resolution {
+    /// The relations of the FROM list and the joins, in order.
+    pub relations: Vec<RelationInstance>,
+    /// Names the select list gives its expressions. WHERE, HAVING and ORDER BY may refer to
+    /// them, and such references are not bound to any relation.
+    pub output_aliases: Vec<String>,
+    /// The subqueries of the query's conditions, in the order they appear.
+    pub subqueries: Vec<Resolution>,
+    /// Bindings by the reference as written, `(qualifier, column)`.
+    bindings: HashMap<(Option<String>, String), ResolvedColumn>,
+    /// The two sides of every `USING` column, by column name.
+    using: HashMap<String, (ResolvedColumn, ResolvedColumn)>,
+}
+
+impl Resolution {
+    /// The relation instance the query refers to as `name`.
+    pub fn relation(&self, name: &str) -> Option<&RelationInstance> {
+        self.relations.iter().find(|r| r.name == name)
+    }
+
+    /// What `column` refers to, or `None` for references to output aliases.
+    pub fn get(&self, column: &nom_sql::Column) -> Option<&ResolvedColumn> {
+        self.bindings.get(&(column.table.clone(), column.name.clone()))
+    }
+
+    /// The column `resolved` refers to, if it is one of this query's relations.
+    pub fn column(&self, resolved: &ResolvedColumn) -> Option<&Column> {
+        self.relation(&resolved.relation)?.columns.iter().find(|c| c.name == resolved.column)
+    }
+
+    /// Every bound reference, sorted.
+    pub fn columns(&self) -> Vec<&ResolvedColumn> {
+        let mut columns: Vec<&ResolvedColumn> = self.bindings.values().collect();
+        columns.sort();
+        columns.dedup();
+        columns
+    }
+
+    /// `column` qualified with its relation instance, or as written if it is an output alias.
+    pub fn qualify(&self, column: &nom_sql::Column) -> nom_sql::Column {
+        self.get(column).map_or_else(|| column.clone(), ResolvedColumn::to_column)
+    }
+
+    /// `predicates` with both sides qualified; `USING` columns become one column of each side.
+    pub fn qualify_predicates(&self, predicates: &[JoinPredicate]) -> Vec<JoinPredicate> {
+        predicates.iter()
+                  .map(|p| match self.using.get(&p.left.name) {
+                      Some((left, right)) if p.left == p.right && p.left.table.is_none() => {
+                          JoinPredicate { left: left.to_column(), right: right.to_column() }
+                      }
+                      _ => JoinPredicate { left: self.qualify(&p.left), right: self.qualify(&p.right) },
+                  })
+                  .collect()
+    }
+}
+
+/// Edit distance between `a` and `b`, ignoring case, counting a swap of two neighbouring
+/// characters as one edit.
+fn distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.to_lowercase().chars().collect();
+    let b: Vec<char> = b.to_lowercase().chars().collect();
+    let mut d: Vec<Vec<usize>> = (0..=a.len()).map(|i| {
+        (0..=b.len()).map(|j| if i == 0 { j } else if j == 0 { i } else { 0 }).collect()
+    }).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let substitution = d[i - 1][j - 1] + (a[i - 1] != b[j - 1]) as usize;
+            d[i][j] = (d[i - 1][j] + 1).min(d[i][j - 1] + 1).min(substitution);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+/// The candidates closest to `name`, for "did you mean" hints: those within a third of its
+/// length in edits, or that contain it or are contained in it.
+fn suggest(name: &str, candidates: Vec<String>, key: impl Fn(&str) -> String) -> Vec<String> {
+    let lower = name.to_lowercase();
+    let limit = (name.chars().count() / 3).max(1);
+    let mut scored: Vec<(usize, String)> = candidates.into_iter()
+        .filter_map(|c| {
+            let k = key(&c).to_lowercase();
+            let d = distance(&lower, &k);
+            if d <= limit || (!k.is_empty() && (k.contains(&lower) || lower.contains(&k))) {
+                Some((d, c))
+            } else {
+                None
+            }
+        })
+        .collect();
+    scored.sort();
+    scored.dedup_by(|a, b| a.1 == b.1);
+    scored.into_iter().take(MAX_SUGGESTIONS).map(|(_, c)| c).collect()
+}
+
+/// The relations and output aliases visible to one SELECT, and those of the queries it is
+/// nested in.
+struct Scope<'a> {
+    relations: &'a [RelationInstance],
+    aliases: &'a [String],
+    outer: Option<&'a Scope<'a>>,
+}
+
+impl<'a> Scope<'a> {
+    fn levels(&self) -> Vec<&Scope<'a>> {
+        let mut levels = vec![self];
+        while let Some(outer) = levels.last().unwrap().outer {
+            levels.push(outer);
+        }
+        levels
+    }
+
+    /// Every column in scope, qualified.
+    fn candidates(&self) -> Vec<String> {
+        self.levels()
+            .iter()
+            .flat_map(|level| level.relations.iter())
+            .flat_map(|r| r.column_names())
+            .collect()
+    }
+
+    /// Binds `column`. `Ok(None)` means it names an output alias.
+    fn lookup(&self, column: &nom_sql::Column, at: &Location) -> Result<Option<ResolvedColumn>, PlanError> {
+        let levels = self.levels();
+        match column.table {
+            Some(ref qualifier) => {
+                let relations = || levels.iter().flat_map(|level| level.relations.iter());
+                // a table that has an alias may still be named by its table name if that is unique
+                let by_table: Vec<&RelationInstance> = relations().filter(|r| r.table == *qualifier).collect();
+                let relation = relations().find(|r| r.name == *qualifier)
+                                          .or(if by_table.len() == 1 { Some(by_table[0]) } else { None });
+                let written = format!("{}.{}", qualifier, column.name);
+                match relation {
+                    Some(relation) => relation.column(&column.name).map(Some).ok_or_else(|| {
+                        let candidates = relation.column_names().collect();
+                        PlanError::UnknownColumn { at: at.clone(), column: written,
+                                                   suggestions: suggest(&column.name, candidates, unqualified) }
+                    }),
+                    None => Err(PlanError::UnknownColumn {
+                        at: at.clone(),
+                        suggestions: suggest(&written, self.candidates(), |c| String::from(c)),
+                        column: written,
+                    }),
+                }
+            }
+            None => {
+                for level in levels.iter() {
+                    let matches: Vec<ResolvedColumn> = level.relations.iter()
+                                                            .filter_map(|r| r.column(&column.name))
+                                                            .collect();
+                    match matches.len() {
+                        0 if level.aliases.contains(&column.name) => return Ok(None),
+                        0 => (),
+                        1 => return Ok(matches.into_iter().next()),
+                        _ => return Err(PlanError::AmbiguousColumn {
+                            at: at.clone(),
+                            column: column.name.clone(),
+                            candidates: matches.iter().map(|m| m.to_string()).collect(),
+                        }),
+                    }
+                }
+                Err(PlanError::UnknownColumn {
+                    at: at.clone(),
+                    column: column.name.clone(),
+                    suggestions: suggest(&column.name, self.candidates(), unqualified),
+                })
+            }
+        }
+    }
+}
+
+fn unqualified(c: &str) -> String {
+    String::from(c.rsplit('.').next().unwrap_or(c))
+}
+
+/// Collects the bindings of one SELECT.
+struct Resolver<'a> {
+    tables: &'a HashMap<String, NodeIndex>,
+    graph: &'a Graph,
+    at: &'a Location,
+    resolution: Resolution,
+}
+
+impl<'a> Resolver<'a> {
+    fn bind(&mut self, scope: &Scope, column: &nom_sql::Column) -> Result<(), PlanError> {
+        if let Some(ref function) = column.function {
+            // an aggregate binds its argument, not the name it is output under
+            return match **function {
+                FunctionExpression::CountStar => Ok(()),
+                FunctionExpression::Avg(ref c, _) |
+                FunctionExpression::Count(ref c, _) |
+                FunctionExpression::Sum(ref c, _) |
+                FunctionExpression::Max(ref c) |
+                FunctionExpression::Min(ref c) |
+                FunctionExpression::GroupConcat(ref c, _) => self.bind(scope, c),
+            };
+        }
+        if let Some(resolved) = scope.lookup(column, self.at)? {
+            self.resolution.bindings.insert((column.table.clone(), column.name.clone()), resolved);
+        }
+        Ok(())
+    }
+
+    fn bind_condition(&mut self, scope: &Scope, cond: &ConditionExpression) -> Result<(), PlanError> {
+        match *cond {
+            ConditionExpression::ComparisonOp(ref tree) | ConditionExpression::LogicalOp(ref tree) => {
+                self.bind_condition(scope, &tree.left)?;
+                self.bind_condition(scope, &tree.right)
+            }
+            ConditionExpression::NegationOp(ref inner) | ConditionExpression::Bracketed(ref inner) => {
+                self.bind_condition(scope, inner)
+            }
+            ConditionExpression::Base(ConditionBase::Field(ref column)) => self.bind(scope, column),
+            ConditionExpression::Base(ConditionBase::NestedSelect(ref select)) => {
+                let nested = resolve(select, self.tables, self.graph, self.at, Some(scope))?;
+                self.resolution.subqueries.push(nested);
+                Ok(())
+            }
+            ConditionExpression::Base(_) => Ok(()),
+        }
+    }
+
+    fn instance(&self, table: &nom_sql::Table) -> Result<RelationInstance, PlanError> {
+        let node = self.tables.get(&table.name)
+                       .ok_or_else(|| PlanError::UnknownTable { at: self.at.clone(), table: table.name.clone() })?;
+        Ok(RelationInstance {
+            name: table.alias.clone().unwrap_or_else(|| table.name.clone()),
+            table: table.name.clone(),
+            columns: self.graph.output_columns(*node),
+        })
+    }
+}
+
+/// Output name of a select list entry, if it gives one other than a plain column name.
+fn output_alias(field: &FieldDefinitionExpression) -> Option<&String> {
+    match *field {
+        FieldDefinitionExpression::Col(ref c) => c.alias.as_ref(),
+        FieldDefinitionExpression::Value(FieldValueExpression::Arithmetic(ref a)) => a.alias.as_ref(),
+        FieldDefinitionExpression::Value(FieldValueExpression::Literal(ref l)) => l.alias.as_ref(),
+        _ => None,
+    }
+}
+
+fn resolve(s: &SelectStatement, tables: &HashMap<String, NodeIndex>, graph: &Graph, at: &Location,
+           outer: Option<&Scope>) -> Result<Resolution, PlanError> {
+    let mut resolver = Resolver { tables, graph, at, resolution: Resolution::default() };
+    let mut relations = Vec::new();
+    for table in s.tables.iter() {
+        relations.push(resolver.instance(table)?);
+    }
+    // USING columns are checked against the relations joined so far
+    let mut using = HashMap::new();
+    for j in s.join.iter() {
+        let right = match j.right {
+            JoinRightSide::Table(ref t) => resolver.instance(t)?,
+            _ => return Err(PlanError::NestedJoin { at: at.clone() }),
+        };
+        if let JoinConstraint::Using(ref columns) = j.constraint {
+            for column in columns.iter() {
+                let left = Scope { relations: &relations, aliases: &[], outer: None }.lookup(column, at)?;
+                let right_column = right.column(&column.name).ok_or_else(|| PlanError::UnknownColumn {
+                    at: at.clone(),
+                    column: format!("{}.{}", right.name, column.name),
+                    suggestions: suggest(&column.name, right.column_names().collect(), unqualified),
+                })?;
+                if let Some(left) = left {
+                    using.insert(column.name.clone(), (left, right_column));
+                }
+            }
+        }
+        relations.push(right);
+    }
+    let aliases: Vec<String> = s.fields.iter().filter_map(output_alias).cloned().collect();
+    let scope = Scope { relations: &relations, aliases: &aliases, outer };
+
+    for field in s.fields.iter() {
+        match *field {
+            FieldDefinitionExpression::All => (),
+            FieldDefinitionExpression::AllInTable(ref table) => {
+                if !relations.iter().any(|r| r.name == *table || r.table == *table) {
+                    return Err(PlanError::UnknownTable { at: at.clone(), table: table.clone() });
+                }
+            }
+            FieldDefinitionExpression::Col(ref column) => resolver.bind(&scope, column)?,
+            FieldDefinitionExpression::Value(FieldValueExpression::Arithmetic(ref a)) => {
+                for operand in [&a.left, &a.right].iter() {
+                    if let ArithmeticBase::Column(ref column) = **operand {
+                        resolver.bind(&scope, column)?;
+                    }
+                }
+            }
+            FieldDefinitionExpression::Value(FieldValueExpression::Literal(_)) => (),
+        }
+    }
+    for j in s.join.iter() {
+        if let JoinConstraint::On(ref cond) = j.constraint {
+            resolver.bind_condition(&scope, cond)?;
+        }
+    }
+    if let Some(ref cond) = s.where_clause {
+        resolver.bind_condition(&scope, cond)?;
+    }
+    if let Some(ref group_by) = s.group_by {
+        for column in group_by.columns.iter() {
+            resolver.bind(&scope, column)?;
+        }
+        if let Some(ref having) = group_by.having {
+            resolver.bind_condition(&scope, having)?;
+        }
+    }
+    if let Some(ref order) = s.order {
+        for (column, _) in order.columns.iter() {
+            resolver.bind(&scope, column)?;
+        }
+    }
+
+    let mut resolution = resolver.resolution;
+    resolution.relations = relations;
+    resolution.output_aliases = aliases;
+    resolution.using = using;
+    Ok(resolution)
+}
+
+/// Binds every column reference of `s` — in the select list, join conditions, WHERE, GROUP BY,
+/// HAVING, ORDER BY and the subqueries of its conditions — to the relation instance it reads,
+/// given the tables and views planned so far. Unknown columns are reported with the most
+/// similar columns in scope, ambiguous ones with every relation that has them.
+pub fn resolve_select(s: &SelectStatement, tables: &HashMap<String, NodeIndex>, graph: &Graph,
+                      at: &Location) -> Result<Resolution, PlanError> {
+    resolve(s, tables, graph, at, None)
+}
@@ -0,0 +1,233 @@
+extern crate serde_json;
+
+use graph::{Column, Graph, NodeIndex, TestNode, TestNodeData};
+use report::{PlanError, QueryResult};
+use strategy::JoinPredicate;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+
+/// A `TestNode` with its edges replaced by node indices.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SavedNode {
+    pub index: usize,
+    pub name: String,
+    pub data: TestNodeData,
+    pub columns: Vec<Column>,
+    pub maxrows: usize,
+    pub ancestors: Vec<usize>,
+    pub children: Vec<usize>,
+    #[serde(default)]
+    pub users: BTreeSet<String>,
+    #[serde(default)]
+    pub join_keys: Vec<JoinPredicate>,
+    #[serde(default)]
+    pub reader_key: Vec<Column>,
+}
+
+/// Which node produces the output of a planned query.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SavedQuery {
+    pub name: Option<String>,
+    pub sql: String,
+    pub leaf: usize,
+}
+
+impl SavedQuery {
+    /// The name if there is one, otherwise the SQL text, as the query's nodes know it. Only
+    /// graphs saved before every SELECT was named `query_<n>` have queries without a name.
+    pub fn label(&self) -> &str {
+        match self.name {
+            Some(ref name) => name,
+            None => self.sql.trim(),
+        }
+    }
+}
+
+/// A planned graph in a form that can be written to disk and loaded back.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SavedGraph {
+    pub nodes: Vec<SavedNode>,
+    pub tables: BTreeMap<String, usize>,
+    pub queries: Vec<SavedQuery>,
+}
+
+impl SavedGraph {
+    pub fn new(graph: &Graph, tables: &HashMap<String, NodeIndex>, queries: &[QueryResult]) -> Self {
+        let nodes = graph.iter()
+                         .map(|n| SavedNode {
+                             index: n.index,
+                             name: n.name.clone(),
+                             data: n.data.clone(),
+                             columns: n.columns.clone(),
+                             maxrows: n.maxrows,
+                             ancestors: n.ancestors.clone(),
+                             children: n.children.clone(),
+                             users: n.users.clone(),
+                             join_keys: n.join_keys.clone(),
+                             reader_key: n.reader_key.clone(),
+                         })
+                         .collect();
+        let tables = tables.iter()
+                           .map(|(name, &node)| (name.clone(), node))
+                           .collect();
+        let queries = queries.iter()
+                             .map(|q| SavedQuery { name: Some(q.name.clone()), sql: q.sql.clone(), leaf: q.leaf })
+                             .collect();
+        SavedGraph { nodes, tables, queries }
+    }
+
+    /// Rebuilds the live graph and table map. Node `i` of the result is `nodes[i]`. Every
+    /// query is made a user of the nodes its leaf depends on, which graphs saved before nodes
+    /// recorded their users rely on to drop queries one at a time. Fails if a node is out of
+    /// place or an edge, table or query refers to a node that does not exist.
+    pub fn to_graph(&self) -> Result<(Graph, HashMap<String, NodeIndex>), PlanError> {
+        self.validate()?;
+        let mut graph = Graph::new();
+        for n in self.nodes.iter() {
+            graph.push_node(TestNode {
+                name: n.name.clone(),
+                index: n.index,
+                data: n.data.clone(),
+                columns: n.columns.clone(),
+                ancestors: n.ancestors.clone(),
+                children: n.children.clone(),
+                maxrows: n.maxrows,
+                users: n.users.clone(),
+                join_keys: n.join_keys.clone(),
+                reader_key: n.reader_key.clone(),
+            });
+        }
+        for q in self.queries.iter() {
+            let user = q.label();
+            for node in graph.ancestor_closure(q.leaf) {
+                graph[node].users.insert(String::from(user));
+            }
+        }
+        let tables = self.tables.iter()
+                                .map(|(name, &i)| (name.clone(), i))
+                                .collect();
+        Ok((graph, tables))
+    }
+
+    fn validate(&self) -> Result<(), PlanError> {
+        let invalid = |reason: String| Err(PlanError::InvalidGraph { reason });
+        let len = self.nodes.len();
+        for (i, n) in self.nodes.iter().enumerate() {
+            if n.index != i {
+                return invalid(format!("node {} is listed as node {}", n.index, i));
+            }
+            if let Some(&edge) = n.ancestors.iter().chain(n.children.iter()).find(|&&e| e >= len) {
+                return invalid(format!("node {} has an edge to node {}, but there are {} nodes", i, edge, len));
+            }
+        }
+        if let Some((name, &node)) = self.tables.iter().find(|&(_, &node)| node >= len) {
+            return invalid(format!("table {} is node {}, but there are {} nodes", name, node, len));
+        }
+        if let Some(q) = self.queries.iter().find(|q| q.leaf >= len) {
+            return invalid(format!("query '{}' reads node {}, but there are {} nodes", q.label(), q.leaf, len));
+        }
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn from_json(s: &str) -> io::Result<Self> {
+        serde_json::from_str(s).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        File::create(path)?.write_all(self.to_json().as_bytes())
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut s = String::new();
+        File::open(path)?.read_to_string(&mut s)?;
+        SavedGraph::from_json(&s)
+    }
+}
+
+
+#[test]
+fn test_saved_graph_round_trip() {
+    use graph::plan_queries;
+    use {load_queries, Optimizations};
+
+    let report = plan_queries(&load_queries(Path::new("tests/tpc-w-queries.txt")).unwrap(), &Optimizations::new());
+    let saved = report.saved_graph();
+    let loaded = SavedGraph::from_json(&saved.to_json()).unwrap();
+    assert_eq!(loaded, saved);
+
+    let (graph, tables) = loaded.to_graph().unwrap();
+    assert_eq!(graph.len(), report.graph.len());
+    for (a, b) in graph.iter().zip(report.graph.iter()) {
+        assert_eq!((&a.name, a.index, &a.data, &a.columns, a.maxrows), (&b.name, b.index, &b.data, &b.columns, b.maxrows));
+        assert_eq!(a.users, b.users);
+        assert_eq!(a.join_keys, b.join_keys);
+        assert_eq!(a.reader_key, b.reader_key);
+        assert_eq!(a.ancestors, b.ancestors);
+        assert_eq!(a.children, b.children);
+    }
+    let mut names: Vec<&String> = tables.keys().collect();
+    let mut expected: Vec<&String> = report.tables.keys().collect();
+    names.sort();
+    expected.sort();
+    assert_eq!(names, expected);
+    for (name, node) in tables.iter() {
+        assert_eq!(*node, report.tables[name]);
+    }
+}
+
+#[test]
+fn test_drop_from_graph_saved_without_users() {
+    use graph::{extend_queries, plan_queries};
+    use {load_queries, Optimizations};
+
+    let queries = load_queries(Path::new("tests/combo-join.txt")).unwrap();
+    let report = plan_queries(&queries, &Optimizations::new());
+    let drop = [String::from("DROP VIEW getMostRecentOrder_id;")];
+    let expected = extend_queries(report.graph.clone(), report.tables.clone(), &drop, &Optimizations::new());
+
+    // as written before nodes recorded their users
+    let mut json: serde_json::Value = serde_json::from_str(&report.saved_graph().to_json()).unwrap();
+    for node in json["nodes"].as_array_mut().unwrap() {
+        node.as_object_mut().unwrap().remove("users");
+    }
+    let (graph, tables) = SavedGraph::from_json(&json.to_string()).unwrap().to_graph().unwrap();
+    let dropped = extend_queries(graph, tables, &drop, &Optimizations::new());
+    assert!(!expected.removals[0].freed_nodes.is_empty());
+    assert_eq!(dropped.removals[0].freed_nodes, expected.removals[0].freed_nodes);
+    assert_eq!(dropped.num_nodes(), expected.num_nodes());
+}
+
+#[test]
+fn test_invalid_saved_graphs() {
+    use graph::plan_queries;
+    use {load_queries, Optimizations};
+
+    let report = plan_queries(&load_queries(Path::new("tests/combo-join.txt")).unwrap(), &Optimizations::new());
+    let saved = report.saved_graph();
+    let len = saved.nodes.len();
+    let invalid = |edit: &dyn Fn(&mut SavedGraph)| {
+        let mut broken = saved.clone();
+        edit(&mut broken);
+        match broken.to_graph() {
+            Err(PlanError::InvalidGraph { reason }) => reason,
+            other => panic!("expected an invalid graph, got {:?}", other.map(|(graph, _)| graph.len())),
+        }
+    };
+    assert_eq!(invalid(&|g| g.nodes.swap(0, 1)), "node 1 is listed as node 0");
+    assert_eq!(invalid(&|g| g.nodes[9].ancestors.push(len)),
+               format!("node 9 has an edge to node {}, but there are {} nodes", len, len));
+    assert_eq!(invalid(&|g| g.nodes[0].children.push(len + 1)),
+               format!("node 0 has an edge to node {}, but there are {} nodes", len + 1, len));
+    assert_eq!(invalid(&|g| { g.tables.insert(String::from("orders"), len); }),
+               format!("table orders is node {}, but there are {} nodes", len, len));
+    assert_eq!(invalid(&|g| g.queries[0].leaf = len),
+               format!("query 'getMostRecentOrder_id' reads node {}, but there are {} nodes", len, len));
+}
@@ -0,0 +1,138 @@
+use config::OutputConfig;
+use graph::{Graph, NodeIndex};
+use render::render_with_fallback;
+use report::QueryResult;
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+
+/// The graph right after one statement was planned.
+pub struct Step<'a> {
+    pub sql: &'a str,
+    /// The result for the statement if it was a query or view.
+    pub query: Option<&'a QueryResult>,
+    /// Nodes the statement added.
+    pub new_nodes: Vec<NodeIndex>,
+    pub graph: &'a Graph,
+}
+
+impl<'a> Step<'a> {
+    /// Existing nodes the statement's query depends on.
+    pub fn reused_nodes(&self) -> &[NodeIndex] {
+        self.query.map_or(&[], |q| &q.reused_nodes)
+    }
+}
+
+/// Writes the graph after every statement to numbered files, `<name>-step-001.<format>` and so
+/// on, with the new nodes of each step highlighted and its reused nodes marked. `finish` writes
+/// `<name>-steps.tsv`, which lists the statement behind every step.
+pub struct StepWriter {
+    output: OutputConfig,
+    index: Vec<String>,
+    fell_back: bool,
+    error: Option<io::Error>,
+}
+
+impl StepWriter {
+    /// Snapshots go next to `output.name`, in each of its formats.
+    pub fn new(output: &OutputConfig) -> Self {
+        StepWriter { output: output.clone(), index: Vec::new(), fell_back: false, error: None }
+    }
+
+    pub fn index_name(&self) -> String {
+        format!("{}-steps.tsv", self.output.name)
+    }
+
+    /// Whether an SVG step was drawn by the native renderer because the renderer is missing.
+    pub fn fell_back(&self) -> bool {
+        self.fell_back
+    }
+
+    /// Renders `step`. After the first error nothing more is written; `finish` reports it.
+    pub fn write(&mut self, step: &Step) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(e) = self.try_write(step) {
+            self.error = Some(e);
+        }
+    }
+
+    fn try_write(&mut self, step: &Step) -> io::Result<()> {
+        let number = self.index.len() + 1;
+        let stem = OutputConfig { name: format!("{}-step-{:03}", self.output.name, number), ..self.output.clone() };
+        let mut options = self.output.dot_options();
+        options.new_nodes = step.new_nodes.iter().cloned().collect();
+        options.reused_nodes = step.reused_nodes().iter().cloned().collect();
+        for format in self.output.formats() {
+            let (rendered, fell_back) = render_with_fallback(step.graph, &self.output.renderer, format, &options)?;
+            self.fell_back |= fell_back;
+            File::create(stem.rendered_name(format))?.write_all(&rendered)?;
+        }
+        // the index sits next to the steps, so it names them relative to itself
+        let file = Path::new(&stem.name).file_name().map_or(stem.name.clone(), |f| f.to_string_lossy().into_owned());
+        let sql: Vec<&str> = step.sql.split_whitespace().collect();
+        self.index.push(format!("{}\t{}\t{}\t{}\t{}", number, file, step.new_nodes.len(), step.reused_nodes().len(),
+                                sql.join(" ")));
+        Ok(())
+    }
+
+    /// Writes the index and returns the number of steps, or the first error any step hit.
+    pub fn finish(self) -> io::Result<usize> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        let mut f = File::create(self.index_name())?;
+        writeln!(f, "step\tfile\tnew\treused\tstatement")?;
+        for line in self.index.iter() {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(self.index.len())
+    }
+}
+
+
+#[test]
+fn test_step_snapshots() {
+    use graph::parse_queries;
+    use std::env;
+    use std::fs;
+    use {load_queries, Optimizations};
+
+    let dir = env::temp_dir().join(format!("join-tests-steps-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let output = OutputConfig {
+        name: dir.join("combo").to_str().unwrap().to_string(),
+        format: String::from("dot"),
+        ..OutputConfig::default()
+    };
+    let queries = load_queries(Path::new("tests/combo-join.txt")).unwrap();
+    let mut writer = StepWriter::new(&output);
+    let report = parse_queries(queries.clone(), Optimizations::new(), Some(&dir.join("combo")),
+                               None, Some(&mut writer));
+    let index_name = writer.index_name();
+    assert_eq!(writer.finish().unwrap(), report.parsed_ok);
+
+    let index = fs::read_to_string(&index_name).unwrap();
+    let lines: Vec<Vec<&str>> = index.lines().skip(1).map(|l| l.split('\t').collect()).collect();
+    assert_eq!(lines.len(), report.parsed_ok);
+    assert!(lines[0][4].starts_with("CREATE TABLE"));
+    // every query's step lists what it added and reused
+    for q in report.queries.iter() {
+        let line = lines.iter().find(|l| l[4] == q.sql.split_whitespace().collect::<Vec<_>>().join(" ")).unwrap();
+        assert_eq!(line[2], q.new_nodes.len().to_string());
+        assert_eq!(line[3], q.reused_nodes.len().to_string());
+    }
+
+    // the last step is the final graph, with that statement's nodes highlighted
+    let last = fs::read_to_string(dir.join(format!("{}.dot", lines.last().unwrap()[1]))).unwrap();
+    let q = report.queries.last().unwrap();
+    for &n in q.new_nodes.iter() {
+        let line = last.lines().find(|l| l.trim_start().starts_with(&format!("n{} [", n))).unwrap();
+        assert!(line.contains("color=\"#D62728\", penwidth=\"3\""));
+    }
+    assert_eq!(last.matches("penwidth").count(), q.new_nodes.len() + q.reused_nodes.len());
+    fs::remove_dir_all(&dir).unwrap();
+}
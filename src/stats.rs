@@ -0,0 +1,125 @@
+extern crate nom_sql;
+
+use nom_sql::{SelectSpecification, SelectStatement, SqlQuery};
+use graph::{parse_drop_view, plan_queries};
+use Optimizations;
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+
+/// A summary of a workload: what kinds of statements it has, how many relations its queries
+/// join, and what planning it under one setting produces.
+#[derive(Clone, Debug, Serialize)]
+pub struct WorkloadStats {
+    pub statements: usize,
+    pub tables: usize,
+    pub views: usize,
+    pub selects: usize,
+    pub drops: usize,
+    /// Statements that parsed but do not change the graph, like INSERT or UPDATE.
+    pub other: usize,
+    pub failures: usize,
+    /// Statements that parsed but could not be planned, see `PlanError`.
+    pub skipped: usize,
+    /// Relations joined by each SELECT and view, in workload order.
+    pub relations_per_query: Vec<usize>,
+    /// Rows assumed for each base table created by the workload.
+    pub table_rows: BTreeMap<String, usize>,
+    pub nodes: usize,
+    pub joins: usize,
+    pub outer_joins: usize,
+    pub rows: usize,
+    pub bytes: usize,
+    pub reused: usize,
+    /// Fraction of non-base nodes used by more than one query.
+    pub shared: f64,
+    /// Mean number of queries using a non-base node.
+    pub mean_sharing: f64,
+    pub cost: f64,
+}
+
+impl WorkloadStats {
+    pub fn max_relations(&self) -> usize {
+        self.relations_per_query.iter().cloned().max().unwrap_or(0)
+    }
+
+    pub fn mean_relations(&self) -> f64 {
+        if self.relations_per_query.is_empty() {
+            return 0.0;
+        }
+        self.relations_per_query.iter().sum::<usize>() as f64 / self.relations_per_query.len() as f64
+    }
+}
+
+fn relations(s: &SelectStatement) -> usize {
+    s.tables.len() + s.join.len()
+}
+
+/// Counts the statements in `queries` and plans them with `opts`.
+pub fn workload_stats(queries: &[String], opts: &Optimizations) -> WorkloadStats {
+    let report = plan_queries(queries, opts);
+    let mut stats = WorkloadStats {
+        statements: queries.len(),
+        tables: 0,
+        views: 0,
+        selects: 0,
+        drops: 0,
+        other: 0,
+        failures: report.failures.len(),
+        skipped: report.errors.len(),
+        relations_per_query: Vec::new(),
+        table_rows: BTreeMap::new(),
+        nodes: report.num_nodes(),
+        joins: report.num_joins(),
+        outer_joins: report.num_outer_joins(),
+        rows: report.total_rows(),
+        bytes: report.total_bytes(),
+        reused: report.total_reused(),
+        shared: report.shared_fraction(),
+        mean_sharing: report.mean_sharing(),
+        cost: opts.cost.cost(&report),
+    };
+    for query in queries.iter() {
+        if parse_drop_view(query).is_some() {
+            stats.drops += 1;
+            continue;
+        }
+        match nom_sql::parser::parse_query(query) {
+            Ok(SqlQuery::CreateTable(ref create)) => {
+                stats.tables += 1;
+                stats.table_rows.insert(create.table.name.clone(), opts.statistics.rows_for(&create.table.name));
+            }
+            Ok(SqlQuery::CreateView(ref create)) => {
+                stats.views += 1;
+                if let SelectSpecification::Simple(ref select) = *create.definition {
+                    stats.relations_per_query.push(relations(select));
+                }
+            }
+            Ok(SqlQuery::Select(ref select)) => {
+                stats.selects += 1;
+                stats.relations_per_query.push(relations(select));
+            }
+            Ok(SqlQuery::DropTable(_)) => stats.drops += 1,
+            Ok(_) => stats.other += 1,
+            Err(_) => (),
+        }
+    }
+    stats
+}
+
+impl fmt::Display for WorkloadStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "STATEMENTS: {} ({} tables, {} views, {} selects, {} drops, {} other, {} failed to parse, {} skipped)",
+                 self.statements, self.tables, self.views, self.selects, self.drops, self.other, self.failures,
+                 self.skipped)?;
+        writeln!(f, "RELATIONS PER QUERY: max {}, mean {:.2}", self.max_relations(), self.mean_relations())?;
+        for (table, rows) in self.table_rows.iter() {
+            writeln!(f, "  {}: {} rows", table, rows)?;
+        }
+        writeln!(f, "NUM_NODES: {}\nNUM_JOINS: {} ({} outer)", self.nodes, self.joins, self.outer_joins)?;
+        writeln!(f, "ROWS: {}\nBYTES: {}\nREUSED: {}", self.rows, self.bytes, self.reused)?;
+        writeln!(f, "SHARED_NODES: {:.1}%\nMEAN_SHARING: {:.2}", self.shared * 100.0, self.mean_sharing)?;
+        writeln!(f, "COST: {:.1}", self.cost)
+    }
+}
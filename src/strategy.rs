@@ -0,0 +1,215 @@
+extern crate nom_sql;
+
+use nom_sql::{ConditionBase, ConditionExpression, JoinConstraint, Operator, SelectStatement};
+use graph::{Graph, NodeIndex};
+use pipeline::Pipeline;
+use Optimizations;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, OnceLock, RwLock};
+
+
+/// An equality between columns of two relations, from the WHERE clause or a join's ON/USING.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JoinPredicate {
+    pub left: nom_sql::Column,
+    pub right: nom_sql::Column,
+}
+
+impl JoinPredicate {
+    /// Whether `other` equates the same two columns, in either order.
+    pub fn same_as(&self, other: &JoinPredicate) -> bool {
+        (self.left == other.left && self.right == other.right) || (self.left == other.right && self.right == other.left)
+    }
+}
+
+impl fmt::Display for JoinPredicate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} = {}", self.left, self.right)
+    }
+}
+
+/// Decides how the relations of one SELECT are joined, adding whatever join nodes it needs to
+/// `graph` and returning the node that produces the joined result.
+///
+/// Implement this and pass it to `register_strategy` to plan with a custom strategy; select it by
+/// name through `Optimizations::join_strategy`.
+pub trait JoinStrategy: Send + Sync {
+    /// Name the strategy is registered and selected under.
+    fn name(&self) -> &str;
+
+    /// Plans the join of `relations`, the tables and views the query reads, by name. The
+    /// columns of `predicates` are qualified with those names too, even where the query refers
+    /// to a relation by an alias; `catalog` has the node each relation is read through.
+    fn plan(&self,
+            relations: &[String],
+            predicates: &[JoinPredicate],
+            catalog: &HashMap<String, NodeIndex>,
+            graph: &mut Graph,
+            opts: &Optimizations) -> NodeIndex;
+}
+
+/// Runs `opts.pipeline()`: the explicit pass list if there is one, otherwise the passes the
+/// flags stand for. This is what `make_select` uses unless another strategy is named.
+pub struct PipelineJoins;
+
+impl JoinStrategy for PipelineJoins {
+    fn name(&self) -> &str {
+        "pipeline"
+    }
+
+    fn plan(&self, relations: &[String], _predicates: &[JoinPredicate], catalog: &HashMap<String, NodeIndex>,
+            graph: &mut Graph, opts: &Optimizations) -> NodeIndex {
+        opts.pipeline().run(relations, catalog, graph)
+    }
+}
+
+/// The pipeline the flags build when only the given join mode is set.
+fn mode_pipeline(opts: &Optimizations, permutations: bool, nonprefix: bool, megajoin: bool) -> Pipeline {
+    Pipeline::from_flags(&Optimizations { permutations, nonprefix, megajoin, ..opts.clone() })
+}
+
+/// Joins relations left to right, reusing an existing join when the next pair already has one.
+/// Relations are taken in FROM order, except that a relation sharing no predicate with those
+/// joined so far waits for one that does, so the chain has no cross products it can avoid.
+pub struct PrefixJoins;
+
+impl JoinStrategy for PrefixJoins {
+    fn name(&self) -> &str {
+        "prefix"
+    }
+
+    fn plan(&self, relations: &[String], predicates: &[JoinPredicate], catalog: &HashMap<String, NodeIndex>,
+            graph: &mut Graph, opts: &Optimizations) -> NodeIndex {
+        let ordered = connected_order(relations, predicates);
+        mode_pipeline(opts, false, false, false).run(&ordered, catalog, graph)
+    }
+}
+
+/// `relations` reordered so that each one after the first shares a predicate with an earlier
+/// one where possible, keeping the given order otherwise.
+fn connected_order(relations: &[String], predicates: &[JoinPredicate]) -> Vec<String> {
+    let joins = |a: &String, b: &String| predicates.iter().any(|p| {
+        let (l, r) = (p.left.table.as_ref(), p.right.table.as_ref());
+        (l == Some(a) && r == Some(b)) || (l == Some(b) && r == Some(a))
+    });
+    let mut remaining: Vec<String> = relations.to_vec();
+    let mut ordered: Vec<String> = Vec::new();
+    while !remaining.is_empty() {
+        let next = remaining.iter()
+                            .position(|r| ordered.iter().any(|o| joins(o, r)))
+                            .unwrap_or(0);
+        ordered.push(remaining.remove(next));
+    }
+    ordered
+}
+
+/// Tries every join order and keeps the one with the longest prefix of existing joins.
+pub struct PermutationJoins;
+
+impl JoinStrategy for PermutationJoins {
+    fn name(&self) -> &str {
+        "permutations"
+    }
+
+    fn plan(&self, relations: &[String], _predicates: &[JoinPredicate], catalog: &HashMap<String, NodeIndex>,
+            graph: &mut Graph, opts: &Optimizations) -> NodeIndex {
+        mode_pipeline(opts, true, false, false).run(relations, catalog, graph)
+    }
+}
+
+/// Reuses any existing joins over a subset of the relations, wherever they sit in the join order.
+pub struct NonprefixJoins;
+
+impl JoinStrategy for NonprefixJoins {
+    fn name(&self) -> &str {
+        "nonprefix"
+    }
+
+    fn plan(&self, relations: &[String], _predicates: &[JoinPredicate], catalog: &HashMap<String, NodeIndex>,
+            graph: &mut Graph, opts: &Optimizations) -> NodeIndex {
+        mode_pipeline(opts, false, true, false).run(relations, catalog, graph)
+    }
+}
+
+/// Outer-joins every relation into one shared chain that all queries read from.
+pub struct MegaJoin;
+
+impl JoinStrategy for MegaJoin {
+    fn name(&self) -> &str {
+        "megajoin"
+    }
+
+    fn plan(&self, relations: &[String], _predicates: &[JoinPredicate], catalog: &HashMap<String, NodeIndex>,
+            graph: &mut Graph, opts: &Optimizations) -> NodeIndex {
+        mode_pipeline(opts, false, false, true).run(relations, catalog, graph)
+    }
+}
+
+
+fn registry() -> &'static RwLock<HashMap<String, Arc<dyn JoinStrategy>>> {
+    static REGISTRY: OnceLock<RwLock<HashMap<String, Arc<dyn JoinStrategy>>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        let builtins: Vec<Arc<dyn JoinStrategy>> = vec![Arc::new(PipelineJoins),
+                                                        Arc::new(PrefixJoins),
+                                                        Arc::new(PermutationJoins),
+                                                        Arc::new(NonprefixJoins),
+                                                        Arc::new(MegaJoin)];
+        RwLock::new(builtins.into_iter().map(|s| (s.name().to_string(), s)).collect())
+    })
+}
+
+/// Makes `strategy` available to every planner in this process under `strategy.name()`,
+/// replacing any strategy already registered under that name.
+pub fn register_strategy(strategy: Arc<dyn JoinStrategy>) {
+    registry().write().unwrap().insert(strategy.name().to_string(), strategy);
+}
+
+pub fn get_strategy(name: &str) -> Option<Arc<dyn JoinStrategy>> {
+    registry().read().unwrap().get(name).cloned()
+}
+
+/// Names of all registered strategies, sorted.
+pub fn strategy_names() -> Vec<String> {
+    let mut names: Vec<String> = registry().read().unwrap().keys().cloned().collect();
+    names.sort();
+    names
+}
+
+
+/// Collects the column equalities from the WHERE clause and from every join's ON or USING.
+pub fn join_predicates(s: &SelectStatement) -> Vec<JoinPredicate> {
+    let mut predicates = Vec::new();
+    if let Some(ref cond) = s.where_clause {
+        collect_predicates(cond, &mut predicates);
+    }
+    for j in s.join.iter() {
+        match j.constraint {
+            JoinConstraint::On(ref cond) => collect_predicates(cond, &mut predicates),
+            JoinConstraint::Using(ref columns) => {
+                for col in columns {
+                    predicates.push(JoinPredicate { left: col.clone(), right: col.clone() });
+                }
+            }
+        }
+    }
+    predicates
+}
+
+fn collect_predicates(cond: &ConditionExpression, predicates: &mut Vec<JoinPredicate>) {
+    match *cond {
+        ConditionExpression::LogicalOp(ref tree) if tree.operator == Operator::And => {
+            collect_predicates(&tree.left, predicates);
+            collect_predicates(&tree.right, predicates);
+        }
+        ConditionExpression::ComparisonOp(ref tree) if tree.operator == Operator::Equal => {
+            if let (&ConditionExpression::Base(ConditionBase::Field(ref l)),
+                    &ConditionExpression::Base(ConditionBase::Field(ref r))) = (&*tree.left, &*tree.right) {
+                predicates.push(JoinPredicate { left: l.clone(), right: r.clone() });
+            }
+        }
+        ConditionExpression::Bracketed(ref inner) => collect_predicates(inner, predicates),
+        _ => (),
+    }
+}
@@ -0,0 +1,324 @@
+use graph::{Graph, NodeIndex};
+use graphviz::DotOptions;
+
+use std::collections::HashMap;
+
+
+const COLOR: &str = "#0C6fA9";
+const FONT_SIZE: usize = 14;
+/// Rough average glyph width at `FONT_SIZE`, used to size boxes without measuring text.
+const CHAR_WIDTH: usize = 8;
+const NODE_PADDING: usize = 12;
+const NODE_HEIGHT: usize = 36;
+const LAYER_GAP: usize = 60;
+const NODE_GAP: usize = 24;
+const MARGIN: usize = 20;
+/// Barycenter sweeps tried before keeping the order with the fewest crossings.
+const SWEEPS: usize = 8;
+
+
+/// A point in the layout: either a graph node, or a bend where an edge passes through a layer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Vertex {
+    pub node: Option<NodeIndex>,
+    pub layer: usize,
+    pub width: usize,
+    pub x: usize,
+}
+
+/// A layered drawing of a graph: every node sits in the layer of its depth from the base
+/// tables, and longer edges are split into one segment per layer they cross.
+#[derive(Clone, Debug)]
+pub struct Layout {
+    pub vertices: Vec<Vertex>,
+    /// Vertex ids of each layer, left to right.
+    pub layers: Vec<Vec<usize>>,
+    /// Vertex ids along each edge of the graph, from ancestor to child.
+    pub paths: Vec<Vec<usize>>,
+}
+
+/// Length of the longest path from a node without ancestors to `node`.
+fn depth(graph: &Graph, node: NodeIndex, depths: &mut HashMap<NodeIndex, usize>) -> usize {
+    if let Some(&d) = depths.get(&node) {
+        return d;
+    }
+    let d = graph[node].ancestors
+                       .iter()
+                       .map(|&a| depth(graph, a, depths) + 1)
+                       .max()
+                       .unwrap_or(0);
+    depths.insert(node, d);
+    d
+}
+
+impl Layout {
+    pub fn new(graph: &Graph) -> Self {
+        let mut depths = HashMap::new();
+        let mut layout = Layout { vertices: Vec::new(), layers: Vec::new(), paths: Vec::new() };
+        // vertex id of each node
+        let mut ids = HashMap::new();
+        for node in graph {
+            let layer = depth(graph, node.index, &mut depths);
+            let width = node.label().chars().count() * CHAR_WIDTH + 2 * NODE_PADDING;
+            ids.insert(node.index, layout.add_vertex(Some(node.index), layer, width));
+        }
+        for node in graph {
+            for &child in node.children.iter() {
+                let mut path = vec![ids[&node.index]];
+                for layer in depths[&node.index] + 1..depths[&child] {
+                    path.push(layout.add_vertex(None, layer, 0));
+                }
+                path.push(ids[&child]);
+                layout.paths.push(path);
+            }
+        }
+        layout.reduce_crossings();
+        layout.assign_x();
+        layout
+    }
+
+    fn add_vertex(&mut self, node: Option<NodeIndex>, layer: usize, width: usize) -> usize {
+        let id = self.vertices.len();
+        self.vertices.push(Vertex { node, layer, width, x: 0 });
+        while self.layers.len() <= layer {
+            self.layers.push(Vec::new());
+        }
+        self.layers[layer].push(id);
+        id
+    }
+
+    /// Every edge segment, as (upper vertex, lower vertex).
+    fn segments(&self) -> Vec<(usize, usize)> {
+        self.paths.iter()
+                  .flat_map(|path| path.windows(2).map(|w| (w[0], w[1])))
+                  .collect()
+    }
+
+    /// Number of pairs of edge segments that cross.
+    pub fn crossings(&self) -> usize {
+        let position = self.positions();
+        let segments = self.segments();
+        let mut count = 0;
+        for (i, &(a1, b1)) in segments.iter().enumerate() {
+            for &(a2, b2) in segments[i + 1..].iter() {
+                if self.vertices[a1].layer != self.vertices[a2].layer {
+                    continue;
+                }
+                let (p1, q1, p2, q2) = (position[a1], position[b1], position[a2], position[b2]);
+                if (p1 < p2 && q1 > q2) || (p1 > p2 && q1 < q2) {
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+
+    /// Position of every vertex within its layer.
+    fn positions(&self) -> Vec<usize> {
+        let mut position = vec![0; self.vertices.len()];
+        for layer in self.layers.iter() {
+            for (i, &v) in layer.iter().enumerate() {
+                position[v] = i;
+            }
+        }
+        position
+    }
+
+    /// Reorders `layer` by the mean position of each vertex's neighbors in the adjacent layer.
+    /// Vertices without such neighbors keep their current position.
+    fn sort_by_barycenter(&mut self, layer: usize, neighbors: &HashMap<usize, Vec<usize>>) {
+        let position = self.positions();
+        let mut keyed: Vec<(f64, usize)> = self.layers[layer]
+            .iter()
+            .map(|&v| match neighbors.get(&v) {
+                Some(ns) if !ns.is_empty() => {
+                    (ns.iter().map(|&n| position[n] as f64).sum::<f64>() / ns.len() as f64, v)
+                }
+                _ => (position[v] as f64, v),
+            })
+            .collect();
+        keyed.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        self.layers[layer] = keyed.into_iter().map(|(_, v)| v).collect();
+    }
+
+    /// Alternates downward and upward barycenter sweeps and keeps the best order seen.
+    fn reduce_crossings(&mut self) {
+        let mut above: HashMap<usize, Vec<usize>> = HashMap::new();
+        let mut below: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (upper, lower) in self.segments() {
+            above.entry(lower).or_default().push(upper);
+            below.entry(upper).or_default().push(lower);
+        }
+        let mut best = (self.crossings(), self.layers.clone());
+        for _ in 0..SWEEPS {
+            if best.0 == 0 {
+                break;
+            }
+            for layer in 1..self.layers.len() {
+                self.sort_by_barycenter(layer, &above);
+            }
+            for layer in (0..self.layers.len().saturating_sub(1)).rev() {
+                self.sort_by_barycenter(layer, &below);
+            }
+            let crossings = self.crossings();
+            if crossings < best.0 {
+                best = (crossings, self.layers.clone());
+            }
+        }
+        self.layers = best.1;
+    }
+
+    fn layer_width(&self, layer: &[usize]) -> usize {
+        let widths: usize = layer.iter().map(|&v| self.vertices[v].width).sum();
+        widths + NODE_GAP * layer.len().saturating_sub(1)
+    }
+
+    /// Places vertices left to right in each layer, centering every layer on the widest one.
+    fn assign_x(&mut self) {
+        let widest = self.layers.iter().map(|l| self.layer_width(l)).max().unwrap_or(0);
+        for l in 0..self.layers.len() {
+            let mut x = MARGIN + (widest - self.layer_width(&self.layers[l])) / 2;
+            for i in 0..self.layers[l].len() {
+                let v = self.layers[l][i];
+                self.vertices[v].x = x;
+                x += self.vertices[v].width + NODE_GAP;
+            }
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        let widest = self.layers.iter().map(|l| self.layer_width(l)).max().unwrap_or(0);
+        widest + 2 * MARGIN
+    }
+
+    pub fn height(&self) -> usize {
+        let layers = self.layers.len();
+        layers * NODE_HEIGHT + layers.saturating_sub(1) * LAYER_GAP + 2 * MARGIN
+    }
+
+    fn top(&self, v: usize) -> usize {
+        MARGIN + self.vertices[v].layer * (NODE_HEIGHT + LAYER_GAP)
+    }
+
+    fn center_x(&self, v: usize) -> usize {
+        self.vertices[v].x + self.vertices[v].width / 2
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+     .replace('<', "&lt;")
+     .replace('>', "&gt;")
+     .replace('"', "&quot;")
+}
+
+/// Draws `graph` as SVG with the layered layout of `Layout::new`, base tables at the top.
+pub fn svg(graph: &Graph) -> String {
+    svg_with(graph, &DotOptions::default())
+}
+
+/// Like `svg`, with the nodes `options` highlights outlined in their color. Clustering is not
+/// drawn.
+pub fn svg_with(graph: &Graph, options: &DotOptions) -> String {
+    let layout = Layout::new(graph);
+    let (width, height) = (layout.width(), layout.height());
+    let mut s = String::new();
+
+    // header.
+    s.push_str(&format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\" \
+                         font-family=\"Helvetica, Arial, sans-serif\" font-size=\"{}\">\n",
+                        width, height, width, height, FONT_SIZE));
+    s.push_str(&format!("  <defs>\n    <marker id=\"arrow\" viewBox=\"0 0 10 10\" refX=\"10\" refY=\"5\" \
+                         markerWidth=\"8\" markerHeight=\"8\" orient=\"auto\">\n      \
+                         <path d=\"M 0 0 L 10 5 L 0 10 z\" fill=\"{}\"/>\n    </marker>\n  </defs>\n", COLOR));
+
+    // edges first, so nodes are drawn over them.
+    s.push_str("  <g class=\"edges\">\n");
+    for path in layout.paths.iter() {
+        let last = path.len() - 1;
+        let points: Vec<String> = path.iter()
+            .enumerate()
+            .map(|(i, &v)| {
+                // leave the bottom of the ancestor, enter the top of the child
+                let y = if i == 0 {
+                    layout.top(v) + NODE_HEIGHT
+                } else if i == last {
+                    layout.top(v)
+                } else {
+                    layout.top(v) + NODE_HEIGHT / 2
+                };
+                format!("{},{}", layout.center_x(v), y)
+            })
+            .collect();
+        s.push_str(&format!("    <polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"2\" \
+                             marker-end=\"url(#arrow)\"/>\n",
+                            points.join(" "), COLOR));
+    }
+    s.push_str("  </g>\n");
+
+    // node descriptions.
+    s.push_str("  <g class=\"nodes\">\n");
+    for (v, vertex) in layout.vertices.iter().enumerate() {
+        let node = match vertex.node {
+            Some(node) => &graph[node],
+            None => continue,
+        };
+        let (stroke, stroke_width) = match options.highlight(node.index) {
+            Some(color) => (color, 4),
+            None => (COLOR, 2),
+        };
+        s.push_str(&format!("    <g id=\"n{}\">\n      <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"6\" \
+                             fill=\"white\" stroke=\"{}\" stroke-width=\"{}\"/>\n",
+                            node.index, vertex.x, layout.top(v), vertex.width, NODE_HEIGHT, stroke, stroke_width));
+        s.push_str(&format!("      <text x=\"{}\" y=\"{}\" text-anchor=\"middle\" dominant-baseline=\"central\">{}</text>\n    </g>\n",
+                            layout.center_x(v), layout.top(v) + NODE_HEIGHT / 2, escape(&node.label())));
+    }
+    s.push_str("  </g>\n");
+
+    // footer.
+    s.push_str("</svg>\n");
+
+    s
+}
+
+
+#[test]
+fn test_layout_combo_join() {
+    use graph::plan_queries;
+    use std::path::Path;
+    use {load_queries, Optimizations};
+
+    let report = plan_queries(&load_queries(Path::new("tests/combo-join.txt")).unwrap(), &Optimizations::new());
+    let layout = Layout::new(&report.graph);
+
+    // bases on the first layer, every edge going exactly one layer down
+    for node in report.graph.iter() {
+        let vertex = layout.vertices.iter().find(|v| v.node == Some(node.index)).unwrap();
+        assert_eq!(vertex.layer == 0, node.ancestors.is_empty());
+    }
+    for path in layout.paths.iter() {
+        for w in path.windows(2) {
+            assert_eq!(layout.vertices[w[0]].layer + 1, layout.vertices[w[1]].layer);
+        }
+    }
+    // no two vertices of a layer overlap
+    for layer in layout.layers.iter() {
+        for w in layer.windows(2) {
+            let (a, b) = (&layout.vertices[w[0]], &layout.vertices[w[1]]);
+            assert!(a.x + a.width <= b.x);
+        }
+    }
+
+    // sweeping never keeps an order worse than the one nodes were added in
+    let mut unordered = layout.clone();
+    for layer in unordered.layers.iter_mut() {
+        layer.sort();
+    }
+    assert!(layout.crossings() <= unordered.crossings());
+
+    let svg = svg(&report.graph);
+    assert!(svg.starts_with("<svg "));
+    assert!(svg.trim_end().ends_with("</svg>"));
+    assert_eq!(svg.matches("<rect ").count(), report.num_nodes());
+    assert_eq!(svg.matches("<polyline ").count(), report.graph.iter().map(|n| n.children.len()).sum::<usize>());
+}
@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+
+/// One statement of a workload file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Statement {
+    /// The statement with its comments removed and any naming rewritten into `CREATE VIEW`.
+    pub sql: String,
+    /// Line of the file the statement starts on, counting from one.
+    pub line: usize,
+}
+
+/// Keywords that start a new statement when they begin a line. They let workloads leave out
+/// the `;` between statements that each fit on one line.
+const STATEMENT_KEYWORDS: [&str; 7] = ["CREATE", "DROP", "INSERT", "UPDATE", "DELETE", "SELECT", "ALTER"];
+
+/// Words after which a line starting with `SELECT` continues the statement rather than
+/// starting a new one, as in `CREATE VIEW v AS` followed by the query on the next line.
+const SELECT_CONTINUES_AFTER: [&str; 5] = ["AS", "UNION", "ALL", "EXCEPT", "INTERSECT"];
+
+/// Query name from a `# name` comment: the text before any `:`, with everything but letters,
+/// digits and underscores replaced so it can name a view.
+pub fn comment_name(comment: &str) -> Option<String> {
+    let text = comment.trim_start_matches('#').split(':').next().unwrap_or("").trim();
+    let mut name = String::new();
+    for c in text.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            name.push(c);
+        } else if !name.ends_with('_') {
+            name.push('_');
+        }
+    }
+    let name = name.trim_matches('_');
+    match name.chars().next() {
+        None => None,
+        Some(c) if c.is_ascii_digit() => Some(format!("q_{}", name)),
+        Some(_) => Some(String::from(name)),
+    }
+}
+
+fn first_word(s: &str) -> &str {
+    s.split(|c: char| !c.is_ascii_alphabetic()).next().unwrap_or("")
+}
+
+fn last_word(s: &str) -> &str {
+    s.rsplit(|c: char| !c.is_ascii_alphabetic()).find(|w| !w.is_empty()).unwrap_or("")
+}
+
+/// `s` without the keyword `word` and the whitespace after it, ignoring case.
+fn strip_keyword<'a>(s: &'a str, word: &str) -> Option<&'a str> {
+    let rest = s.get(word.len()..)?;
+    if s[..word.len()].eq_ignore_ascii_case(word) && rest.starts_with(char::is_whitespace) {
+        Some(rest.trim_start())
+    } else {
+        None
+    }
+}
+
+/// A Noria recipe prefix, as (name, definition): `QUERY name:` or `VIEW name:` in any case,
+/// or a plain `name:` before a SELECT. The definition may start on the next line.
+fn recipe_prefix(sql: &str) -> Option<(&str, &str)> {
+    let (rest, keyword) = match strip_keyword(sql, "QUERY").or_else(|| strip_keyword(sql, "VIEW")) {
+        Some(rest) => (rest, true),
+        None => (sql, false),
+    };
+    let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '`')).unwrap_or(rest.len());
+    let name = rest[..end].trim_matches('`');
+    let definition = rest[end..].trim_start().strip_prefix(':')?;
+    let query = definition.trim_start();
+    let select = query.is_empty() || first_word(query).eq_ignore_ascii_case("select");
+    if name.is_empty() || !(keyword || select) {
+        return None;
+    }
+    Some((name, definition))
+}
+
+/// Splits workload text into statements.
+struct Reader {
+    statements: Vec<Statement>,
+    /// Text of the statement being read, and the line it started on.
+    current: String,
+    start: usize,
+    depth: usize,
+    /// Name for the statement being read, if it is a SELECT.
+    name: Option<String>,
+    /// Name from the last `# name` comment, for the next statement to start.
+    next_name: Option<String>,
+    seen: HashMap<String, usize>,
+}
+
+impl Reader {
+    fn is_empty(&self) -> bool {
+        self.current.trim().is_empty()
+    }
+
+    /// Whether `line`, starting at paren depth zero, begins a statement of its own.
+    fn starts_statement(&self, line: &str) -> bool {
+        if recipe_prefix(line).is_some() {
+            return true;
+        }
+        let word = first_word(line).to_ascii_uppercase();
+        if !STATEMENT_KEYWORDS.contains(&word.as_str()) {
+            return false;
+        }
+        if word == "SELECT" {
+            // the query of a `name:` line
+            if recipe_prefix(self.current.trim()).is_some_and(|(_, definition)| definition.trim().is_empty()) {
+                return false;
+            }
+            let before = last_word(&self.current).to_ascii_uppercase();
+            let in_insert = first_word(self.current.trim_start()).eq_ignore_ascii_case("insert");
+            return !in_insert && !SELECT_CONTINUES_AFTER.contains(&before.as_str());
+        }
+        true
+    }
+
+    fn unique(&mut self, name: String) -> String {
+        let count = self.seen.entry(name.clone()).or_insert(0);
+        *count += 1;
+        if *count == 1 { name } else { format!("{}_{}", name, count) }
+    }
+
+    fn finish(&mut self) {
+        let name = self.name.take();
+        if self.is_empty() {
+            self.current.clear();
+            return;
+        }
+        let sql = self.current.trim().to_string();
+        self.current.clear();
+        self.depth = 0;
+        let sql = if let Some((view, definition)) = recipe_prefix(&sql) {
+            format!("CREATE VIEW {} AS {}", view, definition.trim_start())
+        } else if let Some(name) = name.filter(|_| first_word(&sql).eq_ignore_ascii_case("select")) {
+            format!("CREATE VIEW {} AS {}", self.unique(name), sql)
+        } else {
+            sql
+        };
+        let sql = if sql.ends_with(';') { sql } else { sql + "\n" };
+        self.statements.push(Statement { sql, line: self.start });
+    }
+
+    fn push(&mut self, c: char, line: usize) {
+        if self.is_empty() && !c.is_whitespace() {
+            self.current.clear();
+            self.start = line;
+            self.name = self.next_name.take();
+        }
+        self.current.push(c);
+    }
+}
+
+/// Splits `text` into statements. Statements end at a `;` outside string literals and quoted
+/// identifiers, or where a line at paren depth zero starts another statement. `--`, `#` and
+/// `/* */` comments are removed wherever they appear. A line that is only a `# name` comment
+/// names the SELECT after it, and the Noria recipe prefixes `QUERY name:`, `VIEW name:` and
+/// `name:` name the statement they start; both become `CREATE VIEW` statements.
+pub fn split_statements(text: &str) -> Vec<Statement> {
+    let mut reader = Reader {
+        statements: Vec::new(),
+        current: String::new(),
+        start: 1,
+        depth: 0,
+        name: None,
+        next_name: None,
+        seen: HashMap::new(),
+    };
+    let chars: Vec<char> = text.chars().collect();
+    let mut line = 1;
+    let mut i = 0;
+    // the quote character while inside a string literal or quoted identifier
+    let mut quote: Option<char> = None;
+    let mut at_line_start = true;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).cloned();
+        if let Some(q) = quote {
+            reader.push(c, line);
+            if c == '\\' && q != '`' {
+                if let Some(escaped) = next {
+                    reader.push(escaped, line);
+                    line += (escaped == '\n') as usize;
+                    i += 1;
+                }
+            } else if c == q {
+                quote = None;
+            }
+            line += (c == '\n') as usize;
+            i += 1;
+            continue;
+        }
+        if at_line_start && !c.is_whitespace() {
+            at_line_start = false;
+            let rest: String = chars[i..].iter().take_while(|&&c| c != '\n').collect();
+            if c == '#' {
+                reader.next_name = comment_name(&rest);
+            }
+            if reader.depth == 0 && !reader.is_empty() && reader.starts_statement(&rest) {
+                reader.finish();
+            }
+        }
+        match c {
+            '\n' => {
+                reader.push(c, line);
+                line += 1;
+                at_line_start = true;
+            }
+            '#' => {
+                while i + 1 < chars.len() && chars[i + 1] != '\n' {
+                    i += 1;
+                }
+            }
+            '-' if next == Some('-') => {
+                while i + 1 < chars.len() && chars[i + 1] != '\n' {
+                    i += 1;
+                }
+            }
+            '/' if next == Some('*') => {
+                i += 2;
+                while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                    line += (chars[i] == '\n') as usize;
+                    i += 1;
+                }
+                i += 1;
+                reader.push(' ', line);
+            }
+            '\'' | '"' | '`' => {
+                quote = Some(c);
+                reader.push(c, line);
+            }
+            '(' => {
+                reader.depth += 1;
+                reader.push(c, line);
+            }
+            ')' => {
+                reader.depth = reader.depth.saturating_sub(1);
+                reader.push(c, line);
+            }
+            ';' => {
+                reader.push(c, line);
+                reader.finish();
+            }
+            c => reader.push(c, line),
+        }
+        i += 1;
+    }
+    reader.finish();
+    reader.statements
+}
+
+/// Reads the workload file `f` and splits it with `split_statements`.
+pub fn load_statements(f: &Path) -> io::Result<Vec<Statement>> {
+    let mut s = String::new();
+    File::open(f)?.read_to_string(&mut s)?;
+    Ok(split_statements(&s))
+}
+
+
+#[test]
+fn test_split_statements() {
+    assert_eq!(comment_name("# getMostRecentOrder.id : customer, orders"), Some(String::from("getMostRecentOrder_id")));
+    assert_eq!(comment_name("# new nonsensical query 2: customer"), Some(String::from("new_nonsensical_query_2")));
+    assert_eq!(comment_name("# 2nd"), Some(String::from("q_2nd")));
+    assert_eq!(comment_name("#"), None);
+
+    let text = "CREATE TABLE a (id int, name varchar(10))\n\
+                CREATE TABLE b (id int, a_id int) -- no semicolons needed\n\
+                \n\
+                # both\n\
+                SELECT a.name, b.id\n  \
+                  FROM a, b /* a\nblock */\n  \
+                  WHERE a.id = b.a_id AND a.name = 'x;y'; SELECT id FROM b;\n\
+                CREATE VIEW v AS\n\
+                SELECT id FROM a WHERE name = \"it\\\"s -- not a comment\";\n\
+                QUERY q: SELECT id\n  FROM b;\n";
+    let statements = split_statements(text);
+    let sql: Vec<&str> = statements.iter().map(|s| s.sql.as_str()).collect();
+    assert_eq!(sql, vec![
+        "CREATE TABLE a (id int, name varchar(10))\n",
+        "CREATE TABLE b (id int, a_id int)\n",
+        "CREATE VIEW both AS SELECT a.name, b.id\n  FROM a, b  \n  WHERE a.id = b.a_id AND a.name = 'x;y';",
+        "SELECT id FROM b;",
+        "CREATE VIEW v AS\nSELECT id FROM a WHERE name = \"it\\\"s -- not a comment\";",
+        "CREATE VIEW q AS SELECT id\n  FROM b;",
+    ]);
+    let lines: Vec<usize> = statements.iter().map(|s| s.line).collect();
+    assert_eq!(lines, vec![1, 2, 5, 8, 9, 11]);
+
+    // Noria recipe prefixes, in any case and with the query on the next line
+    let text = "query getUser:\n  SELECT id FROM a WHERE id = ?;\n\
+                View `names`: SELECT name FROM a;\n\
+                countB:\nSELECT id FROM b;\n\
+                stories: SELECT id FROM b;\n";
+    let sql: Vec<String> = split_statements(text).into_iter().map(|s| s.sql).collect();
+    assert_eq!(sql, vec![
+        "CREATE VIEW getUser AS SELECT id FROM a WHERE id = ?;",
+        "CREATE VIEW names AS SELECT name FROM a;",
+        "CREATE VIEW countB AS SELECT id FROM b;",
+        "CREATE VIEW stories AS SELECT id FROM b;",
+    ]);
+    assert_eq!(recipe_prefix("queryCount: SELECT 1;"), Some(("queryCount", " SELECT 1;")));
+    assert_eq!(recipe_prefix("a: b"), None);
+}
@@ -0,0 +1,56 @@
+use std::env;
+use std::fs;
+use std::process::{self, Command};
+
+
+/// Runs the command-line tool with `args` and returns its standard output.
+fn run(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_join-tests")).args(args).output().unwrap();
+    assert!(output.status.success(), "{:?} failed: {}", args, String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn joins_line(stats: &str) -> String {
+    String::from(stats.lines().find(|l| l.starts_with("NUM_JOINS")).unwrap())
+}
+
+#[test]
+fn flags_override_config() {
+    let dir = env::temp_dir().join(format!("join-tests-cli-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let passes = dir.join("passes.toml");
+    fs::write(&passes, "[planner]\npasses = [\"match-reuse\"]\n").unwrap();
+    let megajoin = dir.join("megajoin.toml");
+    fs::write(&megajoin, "[planner]\nmegajoin = true\n").unwrap();
+    let input = "tests/tpcw-views.txt";
+
+    // a flag replaces the config's pass list
+    let flag_only = joins_line(&run(&["stats", "-m", input]));
+    assert!(flag_only.ends_with("(2 outer)"), "{}", flag_only);
+    assert_eq!(joins_line(&run(&["stats", "-c", passes.to_str().unwrap(), "-m", input])), flag_only);
+    // and the config's flags, so flags can turn off what the config turned on
+    assert_eq!(joins_line(&run(&["stats", "-c", megajoin.to_str().unwrap(), "-o", "-p", input])),
+               joins_line(&run(&["stats", "-o", "-p", input])));
+    // without flags the config applies
+    assert_eq!(joins_line(&run(&["stats", "-c", megajoin.to_str().unwrap(), input])), flag_only);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn invalid_saved_graph_is_an_error() {
+    let dir = env::temp_dir().join(format!("join-tests-cli-graph-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let graph = dir.join("graph.json");
+    fs::write(&graph, r#"{"nodes": [], "tables": {"orders": 3}, "queries": []}"#).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_join-tests"))
+        .args(["plan", "--from-graph", graph.to_str().unwrap(), "-f", dir.join("out").to_str().unwrap(),
+                "--format", "dot", "tests/combo-join.txt"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid saved graph: table orders is node 3"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
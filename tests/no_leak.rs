@@ -0,0 +1,45 @@
+extern crate join_tests;
+
+use join_tests::{Optimizations, load_queries, plan_queries};
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::path::Path;
+use std::sync::atomic::{AtomicIsize, Ordering};
+
+
+/// Counts bytes currently allocated, so tests can check that planning frees what it allocates.
+struct CountingAlloc;
+
+static LIVE_BYTES: AtomicIsize = AtomicIsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        LIVE_BYTES.fetch_add(layout.size() as isize, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE_BYTES.fetch_sub(layout.size() as isize, Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+
+#[test]
+fn repeated_planning_does_not_leak() {
+    let queries = load_queries(Path::new("tests/combo-join.txt")).unwrap();
+    let opts = Optimizations::new();
+    // warm up anything allocated lazily on first use
+    drop(plan_queries(&queries, &opts));
+
+    let before = LIVE_BYTES.load(Ordering::SeqCst);
+    for _ in 0..20 {
+        let report = plan_queries(&queries, &opts);
+        assert!(report.num_nodes() > 0);
+    }
+    let after = LIVE_BYTES.load(Ordering::SeqCst);
+    assert_eq!(after - before, 0, "planning leaked {} bytes", after - before);
+}